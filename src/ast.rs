@@ -0,0 +1,133 @@
+//! Syntax tree produced by the parser.
+//!
+//! The `Display` impls print nodes back as shell source, which is what job
+//! listings show.
+
+use std::fmt;
+
+use crate::lexer::{RedirOp, Word};
+
+/// A whole input: and-or lists separated by `;` or newlines.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub items: Vec<AndOrList>,
+}
+
+/// `a && b || c`, optionally run in the background with a trailing `&`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOrList {
+    pub first: Pipeline,
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+    pub background: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AndOrOp {
+    And,
+    Or,
+}
+
+/// `a | b | c`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    /// Started with `!`, which inverts the status.
+    pub negated: bool,
+    pub commands: Vec<SimpleCommand>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimpleCommand {
+    /// `NAME=value` words before the command name.
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    /// The fd written before the operator, if any (`2>`).
+    pub fd: Option<u32>,
+    pub op: RedirOp,
+    pub target: Word,
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                // `&` already separates an item from the next one
+                let separator = if self.items[i - 1].background {
+                    " "
+                } else {
+                    "; "
+                };
+                write!(f, "{}", separator)?;
+            }
+            write!(f, "{}", item)?;
+            if item.background {
+                write!(f, " &")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOrList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (op, pipeline) in &self.rest {
+            let op = match op {
+                AndOrOp::And => "&&",
+                AndOrOp::Or => "||",
+            };
+            write!(f, " {} {}", op, pipeline)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let assignments = self.assignments.iter().map(|a| a.to_string());
+        let words = self.words.iter().map(|w| w.to_string());
+        let redirects = self.redirects.iter().map(|r| r.to_string());
+        let all: Vec<String> = assignments.chain(words).chain(redirects).collect();
+        write!(f, "{}", all.join(" "))
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+        write!(f, "{} {}", self.op, self.target)
+    }
+}
@@ -0,0 +1,226 @@
+//! Brace expansion: `file.{txt,bak}` and `{1..10}`.
+//!
+//! It runs on the parsed word before any other expansion. Only unquoted
+//! braces and commas count, so `'{a,b}'` and `{a,"b,c"}` keep their quoted
+//! text as it is.
+
+use crate::lexer::{Word, WordPart};
+
+/// A piece of a word as brace expansion sees it: an unquoted character, or
+/// anything else carried along untouched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit<'a> {
+    Char(char),
+    Part(&'a WordPart),
+}
+
+/// The words `word` expands to. A word without a brace expression comes
+/// back as it is.
+pub fn expand(word: &Word) -> Vec<Word> {
+    let mut units = Vec::new();
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(s) => units.extend(s.chars().map(Unit::Char)),
+            _ => units.push(Unit::Part(part)),
+        }
+    }
+    expand_units(&units).iter().map(|w| to_word(w)).collect()
+}
+
+fn expand_units<'a>(units: &[Unit<'a>]) -> Vec<Vec<Unit<'a>>> {
+    for start in 0..units.len() {
+        if units[start] != Unit::Char('{') {
+            continue;
+        }
+        let Some(end) = matching_brace(units, start) else {
+            continue;
+        };
+
+        let inner = &units[start + 1..end];
+        let alternatives: Vec<Vec<Unit>> = match sequence(inner) {
+            Some(items) => items
+                .iter()
+                .map(|item| item.chars().map(Unit::Char).collect())
+                .collect(),
+            None => {
+                let choices = split_commas(inner);
+                // `{a}` is just text, but a brace inside it may still expand
+                if choices.len() < 2 {
+                    continue;
+                }
+                choices.into_iter().flat_map(expand_units).collect()
+            }
+        };
+
+        let preamble = &units[..start];
+        let postscripts = expand_units(&units[end + 1..]);
+        let mut words = Vec::new();
+        for alternative in &alternatives {
+            for postscript in &postscripts {
+                let mut word = preamble.to_vec();
+                word.extend_from_slice(alternative);
+                word.extend_from_slice(postscript);
+                words.push(word);
+            }
+        }
+        return words;
+    }
+    vec![units.to_vec()]
+}
+
+/// Index of the `}` closing the `{` at `start`.
+fn matching_brace(units: &[Unit], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, unit) in units.iter().enumerate().skip(start) {
+        match unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits the inside of braces on the commas that aren't in nested braces.
+fn split_commas<'u, 'a>(units: &'u [Unit<'a>]) -> Vec<&'u [Unit<'a>]> {
+    let mut choices = Vec::new();
+    let mut depth = 0;
+    let mut from = 0;
+    for (i, unit) in units.iter().enumerate() {
+        match unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') => depth -= 1,
+            Unit::Char(',') if depth == 0 => {
+                choices.push(&units[from..i]);
+                from = i + 1;
+            }
+            _ => {}
+        }
+    }
+    choices.push(&units[from..]);
+    choices
+}
+
+/// `x..y` or `x..y..step`, with numbers or single letters. `None` if the
+/// inside of the braces isn't a sequence.
+fn sequence(units: &[Unit]) -> Option<Vec<String>> {
+    let text = units
+        .iter()
+        .map(|unit| match unit {
+            Unit::Char(c) => Some(*c),
+            Unit::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+    let bounds: Vec<&str> = text.split("..").collect();
+    let (from, to, step) = match bounds.as_slice() {
+        [from, to] => (*from, *to, 1),
+        [from, to, step] => (*from, *to, step.parse::<i64>().ok()?),
+        _ => return None,
+    };
+    // The sign of the step doesn't matter, only the bounds give the direction
+    let step = step.unsigned_abs().max(1) as usize;
+
+    if let (Ok(start), Ok(end)) = (from.parse::<i64>(), to.parse::<i64>()) {
+        // `{01..10}`: a leading zero on either bound pads every number
+        let zero_padded = |s: &str| {
+            let digits = s.trim_start_matches(['-', '+']);
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if zero_padded(from) || zero_padded(to) {
+            from.len().max(to.len())
+        } else {
+            0
+        };
+        let numbers = range(start, end, step);
+        return Some(
+            numbers
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        );
+    }
+
+    let start = single_letter(from)?;
+    let end = single_letter(to)?;
+    let letters = range(start as i64, end as i64, step);
+    Some(letters.map(|c| (c as u8 as char).to_string()).collect())
+}
+
+fn single_letter(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+        _ => None,
+    }
+}
+
+/// `start` to `end` inclusive, counting down if `end` is smaller.
+fn range(start: i64, end: i64, step: usize) -> Box<dyn Iterator<Item = i64>> {
+    if start <= end {
+        Box::new((start..=end).step_by(step))
+    } else {
+        Box::new((end..=start).rev().step_by(step))
+    }
+}
+
+fn to_word(units: &[Unit]) -> Word {
+    let mut parts = Vec::new();
+    for unit in units {
+        match unit {
+            Unit::Char(c) => match parts.last_mut() {
+                Some(WordPart::Literal(s)) => s.push(*c),
+                _ => parts.push(WordPart::Literal(c.to_string())),
+            },
+            Unit::Part(part) => parts.push((*part).clone()),
+        }
+    }
+    Word { parts }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::{Token, tokenize};
+
+    fn expand(input: &str) -> Vec<String> {
+        let Ok(tokens) = tokenize(input) else {
+            panic!("can't tokenize {}", input);
+        };
+        let [Token::Word(word)] = tokens.as_slice() else {
+            panic!("{} is not one word", input);
+        };
+        super::expand(word).iter().map(|w| w.unquoted()).collect()
+    }
+
+    #[test]
+    fn alternatives() {
+        assert_eq!(expand("x{a,b}y"), ["xay", "xby"]);
+        assert_eq!(expand("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand("{a,{b,c}}"), ["a", "b", "c"]);
+        assert_eq!(expand("{a,}x"), ["ax", "x"]);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(expand("{1..4}"), ["1", "2", "3", "4"]);
+        assert_eq!(expand("{5..1..2}"), ["5", "3", "1"]);
+        assert_eq!(expand("{01..3}"), ["01", "02", "03"]);
+        assert_eq!(expand("{a..e..2}"), ["a", "c", "e"]);
+    }
+
+    #[test]
+    fn not_expanded() {
+        assert_eq!(expand("{a}"), ["{a}"]);
+        assert_eq!(expand("{a,b"), ["{a,b"]);
+        assert_eq!(expand("'{a,b}'"), ["{a,b}"]);
+        assert_eq!(expand("{1..x}"), ["{1..x}"]);
+    }
+
+    #[test]
+    fn quoted_comma_does_not_split() {
+        assert_eq!(expand(r#"{a,"b,c"}"#), ["a", "b,c"]);
+    }
+}
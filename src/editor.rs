@@ -0,0 +1,660 @@
+//! The interactive line editor: a buffer with a cursor, decoded from raw
+//! terminal input and redrawn after every change.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+
+use crate::history::History;
+use crate::shell::Shell;
+use crate::sys;
+use crate::terminal::RawMode;
+use crate::unicode;
+use crate::{SHELL_BUILTINS, UnbufferedStdin, is_executable};
+
+/// How many killed strings Ctrl+Y and Alt+Y can go back through.
+const KILL_RING_SIZE: usize = 16;
+
+/// How long to wait after ESC for the rest of an escape sequence before
+/// taking it as the Esc key on its own.
+const ESCAPE_TIMEOUT_MS: i32 = 50;
+
+pub enum ReadResult {
+    Line(String),
+    /// Ctrl+C: the line was thrown away.
+    Interrupted,
+    /// Ctrl+D on an empty line, or stdin was closed.
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    Up,
+    Down,
+    KillToEnd,
+    KillToStart,
+    KillWordBack,
+    KillWordForward,
+    Yank,
+    YankPop,
+    ClearScreen,
+    ReverseSearch,
+    Escape,
+    /// Ctrl+G
+    Cancel,
+    Interrupt,
+    EndOfFile,
+    Unknown,
+}
+
+impl Key {
+    fn is_kill(self) -> bool {
+        matches!(
+            self,
+            Key::KillToEnd | Key::KillToStart | Key::KillWordBack | Key::KillWordForward
+        )
+    }
+}
+
+/// Where the text of the last Ctrl+Y or Alt+Y went, so Alt+Y can swap it.
+#[derive(Clone, Copy)]
+struct Yanked {
+    start: usize,
+    len: usize,
+    ring_index: usize,
+}
+
+/// State of a Ctrl+R search. The match is shown in `Editor::buffer`.
+struct Search {
+    query: String,
+    /// History index of the current match.
+    found: Option<usize>,
+    failed: bool,
+    /// What to go back to on Ctrl+G.
+    prompt: String,
+    original: Vec<char>,
+    original_cursor: usize,
+}
+
+#[derive(Default)]
+pub struct Editor {
+    prompt: String,
+    buffer: Vec<char>,
+    /// Index into `buffer` the next character is inserted at.
+    cursor: usize,
+    /// Oldest first. Kept across lines.
+    kill_ring: Vec<String>,
+    /// Consecutive kills grow the same ring entry.
+    last_was_kill: bool,
+    yanked: Option<Yanked>,
+    tab_count: u32,
+    /// Up/Down position: None while editing a new line, which is kept in
+    /// `draft` while browsing.
+    history_index: Option<usize>,
+    draft: Vec<char>,
+    search: Option<Search>,
+}
+
+impl Editor {
+    /// Prints `prompt` and edits a line in raw mode until Enter, Ctrl+C or
+    /// Ctrl+D. The terminal is back in its normal mode when this returns.
+    pub fn read_line(&mut self, prompt: &str, shell: &Shell) -> ReadResult {
+        self.prompt = prompt.to_string();
+        self.buffer.clear();
+        self.cursor = 0;
+        self.last_was_kill = false;
+        self.yanked = None;
+        self.tab_count = 0;
+        self.history_index = None;
+        self.search = None;
+        print!("{}", prompt);
+        let _ = io::stdout().flush();
+
+        let result = {
+            let _raw_mode = RawMode::enable().ok();
+            loop {
+                let Some(key) = read_key() else {
+                    break ReadResult::Eof;
+                };
+                let outcome = if self.search.is_some() {
+                    self.handle_search_key(key, shell)
+                } else {
+                    self.handle_key(key, shell)
+                };
+                match outcome {
+                    Some(result) => break result,
+                    None => {
+                        self.last_was_kill = key.is_kill();
+                        if !matches!(key, Key::Yank | Key::YankPop) {
+                            self.yanked = None;
+                        }
+                        if key != Key::Tab {
+                            self.tab_count = 0;
+                        }
+                    }
+                }
+            }
+        };
+
+        match result {
+            ReadResult::Line(_) => println!(),
+            ReadResult::Interrupted => println!("^C"),
+            ReadResult::Eof => {}
+        }
+        result
+    }
+
+    /// Applies one key. Returns the outcome once the line is finished.
+    fn handle_key(&mut self, key: Key, shell: &Shell) -> Option<ReadResult> {
+        match key {
+            Key::Enter => {
+                self.move_to(self.buffer.len());
+                return Some(ReadResult::Line(self.buffer.iter().collect()));
+            }
+            Key::Interrupt => {
+                self.move_to(self.buffer.len());
+                return Some(ReadResult::Interrupted);
+            }
+            Key::EndOfFile if self.buffer.is_empty() => return Some(ReadResult::Eof),
+            Key::EndOfFile | Key::Delete => {
+                if self.cursor < self.buffer.len() {
+                    let end = unicode::next_boundary(&self.buffer, self.cursor);
+                    self.buffer.drain(self.cursor..end);
+                    self.redraw();
+                }
+            }
+            Key::Char(c) => self.insert(&[c]),
+            Key::Tab => {
+                self.tab_count += 1;
+                self.complete(shell);
+            }
+            Key::Backspace => {
+                if self.cursor == 0 {
+                    return None;
+                }
+                let start = unicode::prev_boundary(&self.buffer, self.cursor);
+                let removed: Vec<char> = self.buffer.drain(start..self.cursor).collect();
+                self.cursor = start;
+                let width = unicode::width(&removed);
+                if self.cursor == self.buffer.len() && width > 0 {
+                    // Move back, overwrite with spaces, move back
+                    let back = "\x08".repeat(width);
+                    print!("{}{}{}", back, " ".repeat(width), back);
+                    let _ = io::stdout().flush();
+                } else {
+                    self.redraw();
+                }
+            }
+            Key::Left => self.move_to(unicode::prev_boundary(&self.buffer, self.cursor)),
+            Key::Right => self.move_to(unicode::next_boundary(&self.buffer, self.cursor)),
+            Key::WordLeft => self.move_to(self.word_start()),
+            Key::WordRight => self.move_to(self.word_end()),
+            Key::Home => self.move_to(0),
+            Key::End => self.move_to(self.buffer.len()),
+            Key::Up | Key::Down => self.browse_history(key == Key::Up, &shell.history),
+            Key::KillToEnd => self.kill(self.cursor, self.buffer.len()),
+            Key::KillToStart => self.kill(0, self.cursor),
+            Key::KillWordBack => {
+                // Like readline's unix-word-rubout: back to the previous blank
+                let mut start = self.cursor;
+                while start > 0 && self.buffer[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.buffer[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.kill(start, self.cursor);
+            }
+            Key::KillWordForward => self.kill(self.cursor, self.word_end()),
+            Key::Yank => self.yank(),
+            Key::YankPop => self.yank_pop(),
+            Key::ClearScreen => {
+                print!("\x1b[H\x1b[2J");
+                self.redraw();
+            }
+            Key::ReverseSearch => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                    failed: false,
+                    prompt: std::mem::take(&mut self.prompt),
+                    original: self.buffer.clone(),
+                    original_cursor: self.cursor,
+                });
+                self.show_search();
+            }
+            Key::Escape | Key::Cancel | Key::Unknown => {}
+        }
+        None
+    }
+
+    /// A key typed during a Ctrl+R search. Typing narrows the search, Ctrl+R
+    /// goes to older matches, Ctrl+G goes back to the original line, and any
+    /// other key takes the match for editing and then acts as usual.
+    fn handle_search_key(&mut self, key: Key, shell: &Shell) -> Option<ReadResult> {
+        let history = &shell.history;
+        let search = self.search.as_mut()?;
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                // The current match may still match the longer query
+                let start = search.found.map_or(history.len(), |i| i + 1);
+                self.search_history(history, start);
+            }
+            Key::Backspace => {
+                search.query.pop();
+                self.search_history(history, history.len());
+            }
+            Key::ReverseSearch => {
+                let start = search.found.unwrap_or(history.len());
+                self.search_history(history, start);
+            }
+            Key::Cancel => {
+                let search = self.search.take()?;
+                self.prompt = search.prompt;
+                self.buffer = search.original;
+                self.cursor = search.original_cursor;
+                self.redraw();
+            }
+            Key::Interrupt => return self.handle_key(key, shell),
+            _ => {
+                self.accept_search();
+                return self.handle_key(key, shell);
+            }
+        }
+        None
+    }
+
+    /// Looks for the query in the history entries before `start`, newest
+    /// first. Without a match the previous one stays up.
+    fn search_history(&mut self, history: &History, start: usize) {
+        let Some(search) = self.search.as_mut() else {
+            return;
+        };
+        search.failed = true;
+        for i in (0..start.min(history.len())).rev() {
+            let entry = history.get(i).unwrap_or_default();
+            if let Some(offset) = entry.find(&search.query) {
+                search.found = Some(i);
+                search.failed = false;
+                self.buffer = entry.chars().collect();
+                self.cursor = entry[..offset].chars().count();
+                break;
+            }
+        }
+        self.show_search();
+    }
+
+    fn show_search(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let label = if search.failed {
+            "(failed reverse-i-search)"
+        } else {
+            "(reverse-i-search)"
+        };
+        self.prompt = format!("{}`{}': ", label, search.query);
+        self.redraw();
+    }
+
+    /// Leaves the search with the match in the buffer. Up/Down then browse
+    /// on from the matched entry.
+    fn accept_search(&mut self) {
+        let Some(search) = self.search.take() else {
+            return;
+        };
+        self.prompt = search.prompt;
+        if self.history_index.is_none() {
+            self.draft = search.original;
+        }
+        if search.found.is_some() {
+            self.history_index = search.found;
+        }
+        self.redraw();
+    }
+
+    fn insert(&mut self, chars: &[char]) {
+        let at_end = self.cursor == self.buffer.len();
+        self.buffer
+            .splice(self.cursor..self.cursor, chars.iter().copied());
+        self.cursor += chars.len();
+        if at_end {
+            print!("{}", unicode::shown(chars));
+            let _ = io::stdout().flush();
+        } else {
+            self.redraw();
+        }
+    }
+
+    fn move_to(&mut self, cursor: usize) {
+        // Zero columns would still move one: `ESC [ 0 D` means `ESC [ 1 D`
+        if cursor < self.cursor {
+            let columns = unicode::width(&self.buffer[cursor..self.cursor]);
+            if columns > 0 {
+                print!("\x1b[{}D", columns);
+            }
+        } else if cursor > self.cursor {
+            let columns = unicode::width(&self.buffer[self.cursor..cursor]);
+            if columns > 0 {
+                print!("\x1b[{}C", columns);
+            }
+        }
+        self.cursor = cursor;
+        let _ = io::stdout().flush();
+    }
+
+    /// Rewrites the prompt and the whole buffer, then puts the terminal
+    /// cursor back where the buffer's cursor is.
+    fn redraw(&self) {
+        print!("\r{}{}\x1b[K", self.prompt, unicode::shown(&self.buffer));
+        let back = unicode::width(&self.buffer[self.cursor..]);
+        if back > 0 {
+            print!("\x1b[{}D", back);
+        }
+        let _ = io::stdout().flush();
+    }
+
+    /// Start of the word before the cursor, words being runs of letters and
+    /// digits.
+    fn word_start(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos > 0 && !unicode::is_word_char(self.buffer[pos - 1]) {
+            pos -= 1;
+        }
+        while pos > 0 && unicode::is_word_char(self.buffer[pos - 1]) {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// End of the word after the cursor.
+    fn word_end(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos < self.buffer.len() && !unicode::is_word_char(self.buffer[pos]) {
+            pos += 1;
+        }
+        while pos < self.buffer.len() && unicode::is_word_char(self.buffer[pos]) {
+            pos += 1;
+        }
+        pos
+    }
+
+    /// Removes `start..end` into the kill ring. Right after another kill the
+    /// text joins the previous entry, on the side it was taken from.
+    fn kill(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        let text: String = self.buffer.drain(start..end).collect();
+        let backwards = start < self.cursor;
+        self.cursor = start;
+
+        match self.kill_ring.last_mut() {
+            Some(last) if self.last_was_kill => {
+                if backwards {
+                    last.insert_str(0, &text);
+                } else {
+                    last.push_str(&text);
+                }
+            }
+            _ => {
+                self.kill_ring.push(text);
+                if self.kill_ring.len() > KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+            }
+        }
+        self.redraw();
+    }
+
+    fn yank(&mut self) {
+        let Some(ring_index) = self.kill_ring.len().checked_sub(1) else {
+            return;
+        };
+        self.insert_yanked(ring_index);
+    }
+
+    /// Alt+Y right after a yank: replaces the yanked text with the entry
+    /// killed before it.
+    fn yank_pop(&mut self) {
+        let Some(yanked) = self.yanked else {
+            return;
+        };
+        self.buffer.drain(yanked.start..yanked.start + yanked.len);
+        self.cursor = yanked.start;
+        let ring_index = yanked
+            .ring_index
+            .checked_sub(1)
+            .unwrap_or(self.kill_ring.len() - 1);
+        self.insert_yanked(ring_index);
+        self.redraw();
+    }
+
+    fn insert_yanked(&mut self, ring_index: usize) {
+        let text: Vec<char> = self.kill_ring[ring_index].chars().collect();
+        self.yanked = Some(Yanked {
+            start: self.cursor,
+            len: text.len(),
+            ring_index,
+        });
+        self.insert(&text);
+    }
+
+    fn browse_history(&mut self, up: bool, history: &History) {
+        let len = history.len();
+        let target = match (up, self.history_index) {
+            (true, None) if len > 0 => Some(len - 1),
+            (true, Some(i)) => Some(i.saturating_sub(1)),
+            (false, Some(i)) if i + 1 < len => Some(i + 1),
+            (false, Some(_)) => None,
+            _ => return,
+        };
+
+        if self.history_index.is_none() {
+            self.draft = self.buffer.clone();
+        }
+        self.history_index = target;
+        self.buffer = match target {
+            Some(i) => history.get(i).unwrap_or_default().chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.cursor = self.buffer.len();
+        self.redraw();
+    }
+
+    /// Completes the command name before the cursor against the builtins and
+    /// the executables on PATH.
+    fn complete(&mut self, shell: &Shell) {
+        let prefix: String = self.buffer[..self.cursor].iter().collect();
+        let matches = command_matches(&prefix, shell.vars.get("PATH"));
+
+        match matches.len() {
+            0 => {
+                // No match: ring the bell
+                print!("\x07");
+                let _ = io::stdout().flush();
+            }
+            1 => {
+                // Single match: complete it
+                let mut completion: Vec<char> = matches[0][prefix.len()..].chars().collect();
+                completion.push(' ');
+                self.insert(&completion);
+            }
+            _ => self.complete_multiple(&prefix, matches),
+        }
+    }
+
+    fn complete_multiple(&mut self, prefix: &str, matches: Vec<String>) {
+        if self.tab_count == 1 {
+            // Longest Common Prefix (LCP) Logic, in characters
+            let mut extra: Vec<char> = matches[0][prefix.len()..].chars().collect();
+            for m in &matches[1..] {
+                let common = m[prefix.len()..]
+                    .chars()
+                    .zip(&extra)
+                    .take_while(|(a, b)| a == *b)
+                    .count();
+                extra.truncate(common);
+            }
+
+            if !extra.is_empty() {
+                self.insert(&extra);
+            } else {
+                print!("\x07"); // Bell if no more common chars
+            }
+        } else {
+            // Double Tab Listing Logic
+            println!(); // New line for the list
+            println!("\r{}\r", matches.join("  "));
+            self.redraw(); // Restore the prompt line
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+fn command_matches(prefix: &str, path_var: Option<&str>) -> Vec<String> {
+    let mut matches = Vec::new();
+
+    // Check Builtins
+    for builtin in SHELL_BUILTINS {
+        if builtin.starts_with(prefix) {
+            matches.push(builtin.to_string());
+        }
+    }
+
+    // Check PATH
+    if let Some(path_var) = path_var {
+        for dir in env::split_paths(path_var) {
+            if let Ok(entries) = fs::read_dir(dir) {
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name.starts_with(prefix)
+                        && is_executable(&entry.path())
+                        && !matches.contains(&name)
+                    {
+                        matches.push(name);
+                    }
+                }
+            }
+        }
+    }
+
+    matches.sort();
+    matches
+}
+
+fn read_byte() -> Option<u8> {
+    let mut byte = [0; 1];
+    // Unbuffered, so that `sys::has_input` sees everything not yet read
+    UnbufferedStdin.read_exact(&mut byte).ok()?;
+    Some(byte[0])
+}
+
+/// Reads one key press, decoding escape sequences. `None` at end of input.
+fn read_key() -> Option<Key> {
+    let key = match read_byte()? {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::EndOfFile,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillToEnd,
+        0x0c => Key::ClearScreen,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillToStart,
+        0x17 => Key::KillWordBack,
+        0x07 => Key::Cancel,
+        0x12 => Key::ReverseSearch,
+        0x19 => Key::Yank,
+        0x1b => read_escape()?,
+        byte if byte < 0x20 => Key::Unknown,
+        byte if byte < 0x80 => Key::Char(byte as char),
+        byte => read_utf8(byte)?,
+    };
+    Some(key)
+}
+
+/// Reads the continuation bytes of a multi-byte UTF-8 character. Malformed
+/// input becomes U+FFFD, like `String::from_utf8_lossy` would show it.
+fn read_utf8(first: u8) -> Option<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Some(Key::Char(char::REPLACEMENT_CHARACTER)),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        bytes.push(read_byte()?);
+    }
+    let c = std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER);
+    Some(Key::Char(c))
+}
+
+/// The rest of a key that started with ESC: a CSI (`ESC [`) or SS3 (`ESC O`)
+/// sequence from a special key, or an Alt+key.
+fn read_escape() -> Option<Key> {
+    if !sys::has_input(0, ESCAPE_TIMEOUT_MS) {
+        return Some(Key::Escape);
+    }
+    let key = match read_byte()? {
+        b'[' => {
+            // Parameters, then a final byte in 0x40..=0x7e
+            let mut params = String::new();
+            let last = loop {
+                match read_byte()? {
+                    byte @ 0x40..=0x7e => break byte,
+                    byte => params.push(byte as char),
+                }
+            };
+            match (last, params.as_str()) {
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', "1;3" | "1;5") => Key::WordRight,
+                (b'D', "1;3" | "1;5") => Key::WordLeft,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'H', _) | (b'~', "1" | "7") => Key::Home,
+                (b'F', _) | (b'~', "4" | "8") => Key::End,
+                (b'~', "3") => Key::Delete,
+                _ => Key::Unknown,
+            }
+        }
+        b'O' => match read_byte()? {
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            _ => Key::Unknown,
+        },
+        b'b' => Key::WordLeft,
+        b'f' => Key::WordRight,
+        b'd' => Key::KillWordForward,
+        b'y' => Key::YankPop,
+        0x7f => Key::KillWordBack,
+        _ => Key::Unknown,
+    };
+    Some(key)
+}
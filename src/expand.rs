@@ -0,0 +1,761 @@
+//! Word expansion: turns parsed `Word`s into the text a command receives.
+//!
+//! Command words first go through brace expansion, which can turn one word
+//! into several.
+//!
+//! Text that comes out of an unquoted expansion is split into fields on
+//! `IFS`. Literal and quoted text never is. Unquoted `*`, `?` and `[` are
+//! then matched against file names.
+
+use thiserror::Error;
+
+use crate::ast::Program;
+use crate::lexer::{ParamExpr, ParamOp, ReplaceMode, Word, WordPart};
+use crate::pattern::Pattern;
+use crate::shell::Shell;
+use crate::sys;
+use crate::variables::is_valid_name;
+use crate::{brace, glob};
+
+/// Splitting on space, tab and newline when `IFS` is unset.
+const DEFAULT_IFS: &str = " \t\n";
+
+#[derive(Debug, Error)]
+pub enum ExpandError {
+    /// `${name:?message}` on an unset or empty parameter.
+    #[error("{0}: {1}")]
+    Unset(String, String),
+    #[error("${0}: cannot assign in this way")]
+    CannotAssign(String),
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+    /// A pattern matched no files while `failglob` is set.
+    #[error("no match: {0}")]
+    NoMatch(String),
+}
+
+/// Expands a word to exactly one string, without field splitting. For the
+/// places that take a single word, such as redirection targets.
+pub fn expand_word(word: &Word, shell: &mut Shell) -> Result<String, ExpandError> {
+    let word = expand_tildes(word, false, shell);
+    let mut out = String::new();
+    expand_parts(&word.parts, false, shell, &mut out)?;
+    Ok(out)
+}
+
+/// Expands the value of `NAME=value`. Like `expand_word`, except that a `~`
+/// after any `:` is expanded too, as in `PATH=~/bin:~/.local/bin`.
+pub fn expand_assignment(value: &Word, shell: &mut Shell) -> Result<String, ExpandError> {
+    let value = expand_tildes(value, true, shell);
+    let mut out = String::new();
+    expand_parts(&value.parts, false, shell, &mut out)?;
+    Ok(out)
+}
+
+/// Replaces tilde prefixes with the directory they stand for. A prefix runs
+/// from an unquoted `~` at the start of the word up to the first `/` or `:`,
+/// and all of it has to be unquoted. The directory is quoted text from then
+/// on, so it is never split or globbed.
+fn expand_tildes(word: &Word, assignment: bool, shell: &Shell) -> Word {
+    let mut parts = Vec::new();
+    for (i, part) in word.parts.iter().enumerate() {
+        let WordPart::Literal(text) = part else {
+            parts.push(part.clone());
+            continue;
+        };
+        let is_last = i == word.parts.len() - 1;
+
+        let mut literal = String::new();
+        let mut rest = text.as_str();
+        let mut at_start = i == 0;
+        loop {
+            if at_start && rest.starts_with('~') {
+                let end = rest.find(['/', ':']).unwrap_or(rest.len());
+                // `~"user"` is not a tilde prefix
+                let whole = end < rest.len() || is_last;
+                if let Some(dir) = whole.then(|| tilde_dir(&rest[1..end], shell)).flatten() {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(WordPart::SingleQuoted(dir));
+                    rest = &rest[end..];
+                }
+            }
+            // In an assignment, each `:` separated item can start with one
+            match rest.find(':').filter(|_| assignment) {
+                Some(colon) => {
+                    literal.push_str(&rest[..=colon]);
+                    rest = &rest[colon + 1..];
+                    at_start = true;
+                }
+                None => {
+                    literal.push_str(rest);
+                    break;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+    }
+    Word { parts }
+}
+
+/// `~`, `~user`, `~+` or `~-`, given what follows the tilde. `None` leaves
+/// the prefix as it was written.
+fn tilde_dir(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "" => match shell.vars.get("HOME") {
+            Some(home) => Some(home.to_string()),
+            None => sys::current_user_home(),
+        },
+        "+" => shell.vars.get("PWD").map(String::from),
+        "-" => shell.vars.get("OLDPWD").map(String::from),
+        user => sys::user_home(user),
+    }
+}
+
+fn expand_parts(
+    parts: &[WordPart],
+    quoted: bool,
+    shell: &mut Shell,
+    out: &mut String,
+) -> Result<(), ExpandError> {
+    for part in parts {
+        match part {
+            WordPart::Literal(s) | WordPart::SingleQuoted(s) => out.push_str(s),
+            WordPart::DoubleQuoted(inner) => expand_parts(inner, true, shell, out)?,
+            WordPart::Escaped(c) => out.push(*c),
+            WordPart::Param(param) => match resolve(param, shell)? {
+                Resolved::Value(value) => out.push_str(&value),
+                Resolved::Word(word) if quoted => expand_parts(&word.parts, true, shell, out)?,
+                Resolved::Word(word) => {
+                    let word = expand_tildes(word, false, shell);
+                    expand_parts(&word.parts, false, shell, out)?
+                }
+            },
+            WordPart::CommandSubst(program) => out.push_str(&command_output(program, shell)),
+        }
+    }
+    Ok(())
+}
+
+/// Expands command words into fields. A word can turn into several fields,
+/// or into none when it was only an unquoted expansion of nothing. Fields
+/// with unquoted wildcards are then replaced by the paths they match.
+pub fn expand_words(words: &[Word], shell: &mut Shell) -> Result<Vec<String>, ExpandError> {
+    let ifs = shell.vars.get("IFS").unwrap_or(DEFAULT_IFS).to_string();
+    let mut fields = Fields {
+        shell,
+        ifs,
+        fields: Vec::new(),
+        current: Vec::new(),
+        has_current: false,
+        after_space: false,
+    };
+    for word in words.iter().flat_map(brace::expand) {
+        let word = expand_tildes(&word, false, fields.shell);
+        fields.expand_parts(&word.parts, false)?;
+        fields.finish_word();
+    }
+
+    let mut expanded = Vec::new();
+    for field in fields.fields {
+        let text: String = field.iter().map(|&(c, _)| c).collect();
+        if !Pattern::new(&field).has_wildcards() {
+            expanded.push(text);
+            continue;
+        }
+
+        let matches = glob::glob(&field, &shell.options);
+        if !matches.is_empty() {
+            expanded.extend(matches);
+        } else if shell.options.failglob {
+            return Err(ExpandError::NoMatch(text));
+        } else if !shell.options.nullglob {
+            // A pattern that matches nothing stays as it was written
+            expanded.push(text);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Expands a word used as a pattern. Quoted parts only match themselves;
+/// the result of an unquoted expansion is a pattern like literal text.
+fn expand_pattern(word: &Word, shell: &mut Shell) -> Result<Pattern, ExpandError> {
+    let mut chars = Vec::new();
+    pattern_chars(&word.parts, false, shell, &mut chars)?;
+    Ok(Pattern::new(&chars))
+}
+
+fn pattern_chars(
+    parts: &[WordPart],
+    quoted: bool,
+    shell: &mut Shell,
+    out: &mut Vec<(char, bool)>,
+) -> Result<(), ExpandError> {
+    for part in parts {
+        match part {
+            WordPart::Literal(s) => out.extend(s.chars().map(|c| (c, quoted))),
+            WordPart::SingleQuoted(s) => out.extend(s.chars().map(|c| (c, true))),
+            WordPart::DoubleQuoted(inner) => pattern_chars(inner, true, shell, out)?,
+            WordPart::Escaped(c) => out.push((*c, true)),
+            WordPart::Param(param) => match resolve(param, shell)? {
+                Resolved::Value(value) => out.extend(value.chars().map(|c| (c, quoted))),
+                Resolved::Word(word) => pattern_chars(&word.parts, quoted, shell, out)?,
+            },
+            WordPart::CommandSubst(program) => {
+                let output = command_output(program, shell);
+                out.extend(output.chars().map(|c| (c, quoted)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs a command substitution and returns its output without the trailing
+/// newlines. Its status becomes `$?` right away.
+fn command_output(program: &Program, shell: &mut Shell) -> String {
+    let (output, status) =
+        crate::capture_output(shell, |shell| crate::execute_program(program, shell));
+    shell.last_status = status;
+    shell.last_substitution_status = Some(status);
+    output.trim_end_matches('\n').to_string()
+}
+
+struct Fields<'a> {
+    shell: &'a mut Shell,
+    ifs: String,
+    /// Finished fields, each character paired with whether it was quoted.
+    fields: Vec<Vec<(char, bool)>>,
+    current: Vec<(char, bool)>,
+    /// Whether `current` is a field even while empty, as `""` is.
+    has_current: bool,
+    /// IFS whitespace just ended a field, so a non-whitespace IFS character
+    /// right after it is part of the same delimiter.
+    after_space: bool,
+}
+
+impl Fields<'_> {
+    fn expand_parts(&mut self, parts: &[WordPart], quoted: bool) -> Result<(), ExpandError> {
+        for part in parts {
+            match part {
+                WordPart::Literal(s) => self.push(s, quoted),
+                WordPart::SingleQuoted(s) => self.push(s, true),
+                WordPart::DoubleQuoted(inner) => {
+                    if inner.is_empty() {
+                        self.has_current = true;
+                    }
+                    self.expand_parts(inner, true)?;
+                }
+                WordPart::Escaped(c) => self.push(&c.to_string(), true),
+                WordPart::Param(param) if param.op.is_none() => self.expand_param(param, quoted),
+                WordPart::Param(param) => match resolve(param, self.shell)? {
+                    Resolved::Value(value) if quoted => self.push(&value, true),
+                    Resolved::Value(value) => self.push_split(&value),
+                    Resolved::Word(word) if quoted => self.expand_op_word(&word.parts, true)?,
+                    Resolved::Word(word) => {
+                        let word = expand_tildes(word, false, self.shell);
+                        self.expand_op_word(&word.parts, false)?
+                    }
+                },
+                WordPart::CommandSubst(program) => {
+                    let output = command_output(program, self.shell);
+                    if quoted {
+                        self.push(&output, true);
+                    } else {
+                        self.push_split(&output);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The word of `${name:-word}` and the like. Unless the whole expansion
+    /// is quoted, its unquoted text gets split too.
+    fn expand_op_word(&mut self, parts: &[WordPart], quoted: bool) -> Result<(), ExpandError> {
+        for part in parts {
+            match part {
+                WordPart::Literal(s) if !quoted => self.push_split(s),
+                _ => self.expand_parts(std::slice::from_ref(part), quoted)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_param(&mut self, param: &ParamExpr, quoted: bool) {
+        // `$@`, `$*` and `${name[@]}` stand for a list of values
+        let (values, star) = match subscript(&param.name) {
+            (name, Some(index @ ("@" | "*"))) => (elements(name, self.shell), index == "*"),
+            (name @ ("@" | "*"), None) => (self.shell.positional.clone(), name == "*"),
+            _ => {
+                let value = param_value(&param.name, self.shell);
+                match quoted {
+                    true => self.push(&value, true),
+                    false => self.push_split(&value),
+                }
+                return;
+            }
+        };
+        match (star, quoted) {
+            // "$@": one field per value, none if there are none
+            (false, true) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        self.end_field();
+                    }
+                    self.push(value, true);
+                }
+            }
+            (_, false) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 && self.has_current {
+                        self.end_field();
+                    }
+                    self.push_split(value);
+                }
+            }
+            // "$*": one field, joined by the first character of IFS
+            (true, true) => {
+                let separator = self.ifs.chars().next().map(String::from);
+                let joined = values.join(separator.as_deref().unwrap_or(""));
+                self.push(&joined, true);
+            }
+        }
+    }
+
+    /// Adds text that is never split. Unless it is `quoted`, wildcards in it
+    /// still take part in pathname expansion.
+    fn push(&mut self, text: &str, quoted: bool) {
+        self.current.extend(text.chars().map(|c| (c, quoted)));
+        self.has_current = true;
+        self.after_space = false;
+    }
+
+    /// Adds the result of an unquoted expansion, splitting it on IFS.
+    fn push_split(&mut self, text: &str) {
+        for c in text.chars() {
+            if !self.ifs.contains(c) {
+                self.current.push((c, false));
+                self.has_current = true;
+                self.after_space = false;
+            } else if matches!(c, ' ' | '\t' | '\n') {
+                // Runs of IFS whitespace only ever end a field
+                if self.has_current {
+                    self.end_field();
+                    self.after_space = true;
+                }
+            } else {
+                // Other IFS characters delimit a field each, even an empty one
+                if self.has_current || !self.after_space {
+                    self.end_field();
+                }
+                self.after_space = false;
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        self.fields.push(std::mem::take(&mut self.current));
+        self.has_current = false;
+    }
+
+    fn finish_word(&mut self) {
+        if self.has_current {
+            self.end_field();
+        }
+        self.after_space = false;
+    }
+}
+
+/// What a `${...}` stands for: a finished value, or a word from the
+/// expansion itself that still has to be expanded in its place.
+enum Resolved<'w> {
+    Value(String),
+    Word(&'w Word),
+}
+
+fn resolve<'w>(param: &'w ParamExpr, shell: &mut Shell) -> Result<Resolved<'w>, ExpandError> {
+    let name = param.name.as_str();
+    let value = lookup(name, shell);
+    let Some(op) = &param.op else {
+        return Ok(Resolved::Value(value.unwrap_or_default()));
+    };
+
+    // Whether `${name:-...}` and friends see the parameter as missing
+    let missing = |colon: bool| value.as_deref().is_none_or(|v| colon && v.is_empty());
+
+    let resolved = match op {
+        ParamOp::Length => {
+            let length = match subscript(name) {
+                ("@" | "*", None) => shell.positional.len(),
+                (name, Some("@" | "*")) => elements(name, shell).len(),
+                _ => value.unwrap_or_default().chars().count(),
+            };
+            Resolved::Value(length.to_string())
+        }
+        ParamOp::Default { colon, word } => match missing(*colon) {
+            true => Resolved::Word(word),
+            false => Resolved::Value(value.unwrap_or_default()),
+        },
+        ParamOp::Alternative { colon, word } => match missing(*colon) {
+            true => Resolved::Value(String::new()),
+            false => Resolved::Word(word),
+        },
+        ParamOp::Assign { colon, word } => match missing(*colon) {
+            true => {
+                if !is_valid_name(name) {
+                    return Err(ExpandError::CannotAssign(name.to_string()));
+                }
+                let value = expand_word(word, shell)?;
+                shell.vars.set(name, value.clone());
+                Resolved::Value(value)
+            }
+            false => Resolved::Value(value.unwrap_or_default()),
+        },
+        ParamOp::Error { colon, word } => match missing(*colon) {
+            true => {
+                let mut message = expand_word(word, shell)?;
+                if message.is_empty() {
+                    message = "parameter null or not set".to_string();
+                }
+                return Err(ExpandError::Unset(name.to_string(), message));
+            }
+            false => Resolved::Value(value.unwrap_or_default()),
+        },
+        ParamOp::RemovePrefix { longest, pattern } => {
+            let pattern = expand_pattern(pattern, shell)?;
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let mut ends: Vec<usize> = (0..=chars.len()).collect();
+            if *longest {
+                ends.reverse();
+            }
+            let rest = match ends.into_iter().find(|&end| pattern.matches(&chars[..end])) {
+                Some(end) => &chars[end..],
+                None => &chars[..],
+            };
+            Resolved::Value(rest.iter().collect())
+        }
+        ParamOp::RemoveSuffix { longest, pattern } => {
+            let pattern = expand_pattern(pattern, shell)?;
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let mut starts: Vec<usize> = (0..=chars.len()).rev().collect();
+            if *longest {
+                starts.reverse();
+            }
+            let rest = match starts.into_iter().find(|&s| pattern.matches(&chars[s..])) {
+                Some(start) => &chars[..start],
+                None => &chars[..],
+            };
+            Resolved::Value(rest.iter().collect())
+        }
+        ParamOp::Replace {
+            mode,
+            pattern,
+            replacement,
+        } => {
+            let value = value.unwrap_or_default();
+            if pattern.parts.is_empty() {
+                return Ok(Resolved::Value(value));
+            }
+            let pattern = expand_pattern(pattern, shell)?;
+            let replacement = expand_word(replacement, shell)?;
+            Resolved::Value(replace(&value, &pattern, &replacement, *mode))
+        }
+        ParamOp::Substring { offset, length } => {
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let len = chars.len() as i64;
+            let number = |word: &Word, shell: &mut Shell| {
+                expand_word(word, shell)?
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| ExpandError::BadSubstitution(param.to_string()))
+            };
+
+            // Negative numbers count from the end
+            let mut start = number(offset, shell)?;
+            if start < 0 {
+                start = (len + start).max(0);
+            }
+            let start = start.min(len);
+            let end = match length {
+                None => len,
+                Some(length) => match number(length, shell)? {
+                    n if n < 0 => len + n,
+                    n => start.saturating_add(n).min(len),
+                },
+            };
+            let text = match end > start {
+                true => chars[start as usize..end as usize].iter().collect(),
+                false => String::new(),
+            };
+            Resolved::Value(text)
+        }
+        ParamOp::Case { upper, all } => {
+            let value = value.unwrap_or_default();
+            let convert = |c: char| -> String {
+                match upper {
+                    true => c.to_uppercase().collect(),
+                    false => c.to_lowercase().collect(),
+                }
+            };
+            let text = match all {
+                true => value.chars().map(convert).collect(),
+                false => {
+                    let mut chars = value.chars();
+                    match chars.next() {
+                        Some(first) => convert(first) + chars.as_str(),
+                        None => String::new(),
+                    }
+                }
+            };
+            Resolved::Value(text)
+        }
+    };
+    Ok(resolved)
+}
+
+/// `${name/pattern/replacement}`: replaces the leftmost longest match, or
+/// every match, or one anchored at either end.
+fn replace(value: &str, pattern: &Pattern, replacement: &str, mode: ReplaceMode) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len();
+    let longest_from = |start: usize, max_end: usize| {
+        (start..=max_end)
+            .rev()
+            .find(|&end| pattern.matches(&chars[start..end]))
+    };
+
+    match mode {
+        ReplaceMode::Prefix => match longest_from(0, len) {
+            Some(end) => replacement.to_string() + &chars[end..].iter().collect::<String>(),
+            None => value.to_string(),
+        },
+        ReplaceMode::Suffix => match (0..=len).find(|&s| pattern.matches(&chars[s..])) {
+            Some(start) => chars[..start].iter().collect::<String>() + replacement,
+            None => value.to_string(),
+        },
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut out = String::new();
+            let mut pos = 0;
+            let mut replaced = false;
+            while pos < len {
+                if !(replaced && mode == ReplaceMode::First)
+                    && let Some(end) = longest_from(pos, len).filter(|&end| end > pos)
+                {
+                    out.push_str(replacement);
+                    pos = end;
+                    replaced = true;
+                    continue;
+                }
+                out.push(chars[pos]);
+                pos += 1;
+            }
+            out
+        }
+    }
+}
+
+/// The value of a parameter, or `None` if it is unset.
+fn lookup(name: &str, shell: &Shell) -> Option<String> {
+    if let (name, Some(index)) = subscript(name) {
+        let elements = elements(name, shell);
+        if matches!(index, "@" | "*") {
+            return (!elements.is_empty()).then(|| elements.join(" "));
+        }
+        // Negative indexes count from the end
+        let index = index.trim().parse::<i64>().ok()?;
+        let index = if index < 0 {
+            elements.len().checked_sub(index.unsigned_abs() as usize)?
+        } else {
+            index as usize
+        };
+        return elements.get(index).cloned();
+    }
+
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "0" => Some(shell.script_name.clone()),
+        "#" => Some(shell.positional.len().to_string()),
+        "@" | "*" if shell.positional.is_empty() => None,
+        "@" | "*" => Some(shell.positional.join(" ")),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.last_background_pid.map(|pid| pid.to_string()),
+        "PIPESTATUS" => shell.pipe_status.first().map(|s| s.to_string()),
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => name
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| shell.positional.get(n.checked_sub(1)?))
+            .cloned(),
+        _ => shell.vars.get(name).map(String::from),
+    }
+}
+
+/// `name[index]` taken apart.
+fn subscript(name: &str) -> (&str, Option<&str>) {
+    match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+        Some((name, index)) => (name, Some(index)),
+        None => (name, None),
+    }
+}
+
+/// The values of an array. `PIPESTATUS` is the only real one; like in bash,
+/// any other variable acts as an array of its one value.
+fn elements(name: &str, shell: &Shell) -> Vec<String> {
+    match name {
+        "PIPESTATUS" => shell.pipe_status.iter().map(|s| s.to_string()).collect(),
+        _ => lookup(name, shell).into_iter().collect(),
+    }
+}
+
+fn param_value(name: &str, shell: &Shell) -> String {
+    lookup(name, shell).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(input: &str) -> Vec<Word> {
+        crate::lexer::tokenize(input)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token {
+                crate::lexer::Token::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `input` expanded with `$x` set to `value`.
+    fn split(ifs: Option<&str>, value: &str, input: &str) -> Vec<String> {
+        let mut shell = Shell::default();
+        shell.vars.set("x", value.to_string());
+        if let Some(ifs) = ifs {
+            shell.vars.set("IFS", ifs.to_string());
+        }
+        expand_words(&words(input), &mut shell).unwrap()
+    }
+
+    #[test]
+    fn default_ifs_splits_on_whitespace_runs() {
+        assert_eq!(split(None, "  a  b\tc\n", "$x"), ["a", "b", "c"]);
+        assert_eq!(split(None, "  a  b ", "\"$x\""), ["  a  b "]);
+    }
+
+    #[test]
+    fn other_ifs_characters_delimit_one_field_each() {
+        assert_eq!(split(Some(":"), "a::b:", "$x"), ["a", "", "b"]);
+        assert_eq!(split(Some(" :"), " a : b ", "$x"), ["a", "b"]);
+    }
+
+    #[test]
+    fn empty_ifs_never_splits() {
+        assert_eq!(split(Some(""), "a b", "$x"), ["a b"]);
+    }
+
+    #[test]
+    fn empty_expansions() {
+        assert!(split(None, "", "$x").is_empty());
+        assert_eq!(split(None, "", "\"$x\""), [""]);
+        assert_eq!(split(None, "", "a$x"), ["a"]);
+    }
+
+    #[test]
+    fn literal_text_is_not_split() {
+        assert_eq!(split(Some(":"), "", "a:b"), ["a:b"]);
+    }
+
+    fn replaced(value: &str, pattern: &str, replacement: &str, mode: ReplaceMode) -> String {
+        let chars: Vec<(char, bool)> = pattern.chars().map(|c| (c, false)).collect();
+        replace(value, &Pattern::new(&chars), replacement, mode)
+    }
+
+    #[test]
+    fn replace_first_and_all() {
+        assert_eq!(replaced("aXbXc", "X", "-", ReplaceMode::First), "a-bXc");
+        assert_eq!(replaced("aXbXc", "X", "-", ReplaceMode::All), "a-b-c");
+        assert_eq!(replaced("abc", "z", "-", ReplaceMode::All), "abc");
+    }
+
+    #[test]
+    fn replace_takes_the_longest_match() {
+        assert_eq!(replaced("aXbXc", "X*", "-", ReplaceMode::First), "a-");
+        assert_eq!(replaced("a.b.c", "?.", "", ReplaceMode::All), "c");
+    }
+
+    #[test]
+    fn replace_anchored() {
+        assert_eq!(replaced("abab", "ab", "X", ReplaceMode::Prefix), "Xab");
+        assert_eq!(replaced("abab", "ab", "X", ReplaceMode::Suffix), "abX");
+        assert_eq!(replaced("abab", "b", "X", ReplaceMode::Prefix), "abab");
+    }
+
+    #[test]
+    fn unmatched_patterns() {
+        let pattern = "/nonexistent-rust-shell/*.none";
+        let mut shell = Shell::default();
+        assert_eq!(
+            expand_words(&words(pattern), &mut shell).unwrap(),
+            [pattern]
+        );
+
+        shell.options.nullglob = true;
+        assert!(
+            expand_words(&words(pattern), &mut shell)
+                .unwrap()
+                .is_empty()
+        );
+
+        shell.options.nullglob = false;
+        shell.options.failglob = true;
+        assert!(matches!(
+            expand_words(&words(pattern), &mut shell),
+            Err(ExpandError::NoMatch(_))
+        ));
+    }
+
+    fn tilde_shell() -> Shell {
+        let mut shell = Shell::default();
+        shell.vars.set("HOME", "/home/me".to_string());
+        shell.vars.set("PWD", "/here".to_string());
+        shell.vars.set("OLDPWD", "/there".to_string());
+        shell
+    }
+
+    fn expanded(input: &str) -> String {
+        expand_word(&words(input)[0], &mut tilde_shell()).unwrap()
+    }
+
+    #[test]
+    fn tilde_prefixes() {
+        assert_eq!(expanded("~"), "/home/me");
+        assert_eq!(expanded("~/x"), "/home/me/x");
+        assert_eq!(expanded("~+"), "/here");
+        assert_eq!(expanded("~-/y"), "/there/y");
+        assert_eq!(expanded("a~"), "a~");
+        assert_eq!(expanded("'~'"), "~");
+        assert_eq!(expanded(r"\~"), "~");
+        assert_eq!(expanded("~\"x\""), "~x");
+        assert_eq!(expanded("~no_such_user_here"), "~no_such_user_here");
+    }
+
+    #[test]
+    fn assignments_expand_after_colons() {
+        let value = &words("~/a:~/b")[0];
+        let mut shell = tilde_shell();
+        assert_eq!(
+            expand_assignment(value, &mut shell).unwrap(),
+            "/home/me/a:/home/me/b"
+        );
+        assert_eq!(expand_word(value, &mut shell).unwrap(), "/home/me/a:~/b");
+    }
+
+    #[test]
+    fn expanded_home_is_not_split() {
+        let mut shell = tilde_shell();
+        shell.vars.set("HOME", "/a b".to_string());
+        assert_eq!(expand_words(&words("~"), &mut shell).unwrap(), ["/a b"]);
+    }
+}
@@ -0,0 +1,207 @@
+//! Pathname expansion: turning `*.rs` into the files it matches.
+//!
+//! A pattern is matched one `/`-separated component at a time, so wildcards
+//! never match a slash.
+
+use std::fs;
+use std::path::Path;
+
+use crate::options::Options;
+use crate::pattern::Pattern;
+
+/// The paths matching `pattern`, sorted. Each character comes with whether
+/// it was quoted, which makes it match only itself. Empty if nothing
+/// matches.
+pub fn glob(pattern: &[(char, bool)], options: &Options) -> Vec<String> {
+    let components: Vec<&[(char, bool)]> = pattern.split(|&(c, _)| c == '/').collect();
+    let (mut paths, components) = match components.split_first() {
+        // A leading `/` leaves an empty first component
+        Some(([], rest)) => (vec!["/".to_string()], rest),
+        _ => (vec![String::new()], &components[..]),
+    };
+
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i == components.len() - 1;
+        if component.is_empty() {
+            // `*/` only matches directories, and keeps the slash
+            if is_last {
+                paths.retain(|path| Path::new(path).is_dir());
+                for path in &mut paths {
+                    path.push('/');
+                }
+            }
+            continue;
+        }
+
+        let is_globstar = options.globstar && matches!(component, [('*', false), ('*', false)]);
+        let pattern = Pattern::new(component);
+        let mut next = Vec::new();
+        for path in &paths {
+            if is_globstar {
+                // Any number of directories, including none. Last in the
+                // pattern, it also stands for the directory it starts from.
+                if !is_last {
+                    next.push(path.clone());
+                } else if !path.is_empty() {
+                    next.push(join(path, ""));
+                }
+                descendants(path, !is_last, options, &mut next);
+            } else if pattern.has_wildcards() {
+                let hidden_ok = options.dotglob || component[0].0 == '.';
+                for name in entries(path) {
+                    let chars: Vec<char> = name.chars().collect();
+                    if (hidden_ok || !name.starts_with('.')) && pattern.matches(&chars) {
+                        next.push(join(path, &name));
+                    }
+                }
+            } else {
+                let name: String = component.iter().map(|&(c, _)| c).collect();
+                let joined = join(path, &name);
+                if fs::symlink_metadata(&joined).is_ok() {
+                    next.push(joined);
+                }
+            }
+        }
+        paths = next;
+    }
+
+    paths.sort();
+    paths
+}
+
+/// Names in the directory `path`, the current one if it is empty.
+fn entries(path: &str) -> Vec<String> {
+    let dir = if path.is_empty() { "." } else { path };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Everything below `path` for `**`, or only the directories. Symlinks are
+/// not followed, so a link back up the tree can't loop.
+fn descendants(path: &str, dirs_only: bool, options: &Options, out: &mut Vec<String>) {
+    for name in entries(path) {
+        if name.starts_with('.') && !options.dotglob {
+            continue;
+        }
+        let joined = join(path, &name);
+        let is_dir = fs::symlink_metadata(&joined).is_ok_and(|m| m.is_dir());
+        if is_dir || !dirs_only {
+            out.push(joined.clone());
+        }
+        if is_dir {
+            descendants(&joined, dirs_only, options, out);
+        }
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() || path.ends_with('/') {
+        format!("{}{}", path, name)
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding a few files, named after the test so tests
+    /// running at the same time don't share it.
+    fn tree(test: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("rust-shell-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        for file in [
+            "a.rs",
+            "b.rs",
+            ".hidden.rs",
+            "notes.txt",
+            "sub/c.rs",
+            "sub/deep/d.rs",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir.to_string_lossy().into_owned()
+    }
+
+    /// Matches of `pattern` inside `dir`, relative to it.
+    fn glob_in(dir: &str, pattern: &[(char, bool)], options: &Options) -> Vec<String> {
+        let mut full: Vec<(char, bool)> = dir.chars().map(|c| (c, true)).collect();
+        full.push(('/', false));
+        full.extend_from_slice(pattern);
+        glob(&full, options)
+            .into_iter()
+            .map(|path| path[dir.len() + 1..].to_string())
+            .collect()
+    }
+
+    fn unquoted(pattern: &str) -> Vec<(char, bool)> {
+        pattern.chars().map(|c| (c, false)).collect()
+    }
+
+    #[test]
+    fn stars_skip_hidden_files() {
+        let dir = tree("stars");
+        let options = Options::default();
+        assert_eq!(glob_in(&dir, &unquoted("*.rs"), &options), ["a.rs", "b.rs"]);
+        assert_eq!(glob_in(&dir, &unquoted(".*.rs"), &options), [".hidden.rs"]);
+        assert!(glob_in(&dir, &unquoted("*.c"), &options).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dotglob() {
+        let dir = tree("dotglob");
+        let options = Options {
+            dotglob: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            glob_in(&dir, &unquoted("*.rs"), &options),
+            [".hidden.rs", "a.rs", "b.rs"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn trailing_slash_matches_directories() {
+        let dir = tree("slash");
+        assert_eq!(
+            glob_in(&dir, &unquoted("*/"), &Options::default()),
+            ["sub/"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn globstar() {
+        let dir = tree("globstar");
+        let pattern = unquoted("**/*.rs");
+        assert_eq!(glob_in(&dir, &pattern, &Options::default()), ["sub/c.rs"]);
+
+        let options = Options {
+            globstar: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            glob_in(&dir, &pattern, &options),
+            ["a.rs", "b.rs", "sub/c.rs", "sub/deep/d.rs"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quoted_wildcards_match_literally() {
+        let dir = tree("quoted");
+        let pattern = [('*', true), ('.', false), ('r', false), ('s', false)];
+        assert!(glob_in(&dir, &pattern, &Options::default()).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
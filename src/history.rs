@@ -0,0 +1,243 @@
+//! Command history: an in-memory list the line editor browses, loaded from
+//! and saved to `$HISTFILE`.
+//!
+//! Entries are saved one per line. An entry spanning several lines, like a
+//! command with a here-document, has a `\` before each newline inside it.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::redirect::BuiltinIo;
+use crate::variables::Variables;
+
+const DEFAULT_SIZE: usize = 1000;
+
+#[derive(Default)]
+pub struct History {
+    /// Oldest first.
+    entries: Vec<String>,
+    /// `HISTSIZE`: how many entries are kept in memory.
+    max_entries: usize,
+    /// `HISTFILESIZE`: how many entries are written back to the file.
+    max_file_entries: usize,
+    file: Option<PathBuf>,
+}
+
+fn size_from_vars(vars: &Variables, name: &str) -> usize {
+    vars.get(name)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SIZE)
+}
+
+impl History {
+    /// Reads the limits and file location from the shell variables and loads
+    /// the file, if there is one.
+    pub fn load(vars: &Variables) -> Self {
+        let file = match vars.get("HISTFILE") {
+            // Set but empty: no history file
+            Some("") => None,
+            Some(file) => Some(PathBuf::from(file)),
+            None => vars
+                .get("HOME")
+                .map(|h| PathBuf::from(h).join(".rust_shell_history")),
+        };
+
+        let mut history = Self {
+            entries: Vec::new(),
+            max_entries: size_from_vars(vars, "HISTSIZE"),
+            max_file_entries: size_from_vars(vars, "HISTFILESIZE"),
+            file,
+        };
+        if let Some(contents) = history
+            .file
+            .as_ref()
+            .and_then(|f| fs::read_to_string(f).ok())
+        {
+            let mut entry = String::new();
+            for line in contents.lines() {
+                match line.strip_suffix('\\') {
+                    Some(start) => {
+                        entry.push_str(start);
+                        entry.push('\n');
+                    }
+                    None => {
+                        entry.push_str(line);
+                        history.add(&entry);
+                        entry.clear();
+                    }
+                }
+            }
+        }
+        history
+    }
+
+    /// Writes the most recent `HISTFILESIZE` entries to the history file.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let skip = self.entries.len().saturating_sub(self.max_file_entries);
+        let mut contents = String::new();
+        for entry in &self.entries[skip..] {
+            contents.push_str(&entry.replace('\n', "\\\n"));
+            contents.push('\n');
+        }
+        fs::write(file, contents)
+    }
+
+    pub fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.max_entries == 0 {
+            return;
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > self.max_entries {
+            let excess = self.entries.len() - self.max_entries;
+            self.entries.drain(..excess);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Removes the entry shown as number `n` by `history`.
+    pub fn remove(&mut self, n: usize) -> bool {
+        if n == 0 || n > self.entries.len() {
+            return false;
+        }
+        self.entries.remove(n - 1);
+        true
+    }
+
+    /// `history [-c] [-d N] [N]`
+    pub fn builtin(&mut self, args: &[String], io: &mut BuiltinIo) -> i32 {
+        match args.first().map(String::as_str) {
+            Some("-c") => {
+                self.clear();
+                0
+            }
+            Some("-d") => {
+                let Some(offset) = args.get(1) else {
+                    io.eprintln("history: -d: option requires an argument");
+                    return 2;
+                };
+                match offset.parse() {
+                    Ok(n) if self.remove(n) => 0,
+                    _ => {
+                        io.eprintln(format!(
+                            "history: {}: history position out of range",
+                            offset
+                        ));
+                        1
+                    }
+                }
+            }
+            Some(count) => match count.parse::<usize>() {
+                Ok(count) => {
+                    self.print(self.entries.len().saturating_sub(count), io);
+                    0
+                }
+                Err(_) => {
+                    io.eprintln(format!("history: {}: numeric argument required", count));
+                    2
+                }
+            },
+            None => {
+                self.print(0, io);
+                0
+            }
+        }
+    }
+
+    fn print(&self, from: usize, io: &mut BuiltinIo) {
+        for (i, entry) in self.entries.iter().enumerate().skip(from) {
+            io.println(format!("{:>5}  {}", i + 1, entry));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(max_entries: usize) -> History {
+        History {
+            max_entries,
+            ..History::default()
+        }
+    }
+
+    fn entries(history: &History) -> Vec<&str> {
+        (0..history.len()).filter_map(|i| history.get(i)).collect()
+    }
+
+    #[test]
+    fn add_skips_blank_lines() {
+        let mut history = history(10);
+        for line in ["ls", "   ", "", "pwd"] {
+            history.add(line);
+        }
+        assert_eq!(entries(&history), ["ls", "pwd"]);
+    }
+
+    #[test]
+    fn histsize_drops_the_oldest() {
+        let mut history = history(2);
+        for line in ["a", "b", "c"] {
+            history.add(line);
+        }
+        assert_eq!(entries(&history), ["b", "c"]);
+
+        let mut none = self::history(0);
+        none.add("a");
+        assert_eq!(none.len(), 0);
+    }
+
+    #[test]
+    fn remove_counts_from_one() {
+        let mut history = history(10);
+        for line in ["a", "b", "c"] {
+            history.add(line);
+        }
+        assert!(!history.remove(0));
+        assert!(!history.remove(4));
+        assert!(history.remove(2));
+        assert_eq!(entries(&history), ["a", "c"]);
+    }
+
+    #[test]
+    fn multi_line_entries_survive_the_file() {
+        let file = std::env::temp_dir().join(format!("rust-shell-history-{}", std::process::id()));
+        let mut vars = Variables::default();
+        vars.set("HISTFILE", file.display().to_string());
+
+        let mut saved = History::load(&vars);
+        for line in ["cat <<EOF\na\\\nb\nEOF", "echo a\\ b"] {
+            saved.add(line);
+        }
+        saved.save().unwrap();
+
+        let loaded = History::load(&vars);
+        assert_eq!(entries(&loaded), entries(&saved));
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn empty_histfile_means_no_file() {
+        let mut vars = Variables::default();
+        vars.set("HOME", "/home/me".to_string());
+        vars.set("HISTFILE", String::new());
+        let history = History::load(&vars);
+        assert!(history.file.is_none());
+        assert!(history.save().is_ok());
+    }
+}
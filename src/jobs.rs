@@ -0,0 +1,558 @@
+//! Job control: the job table, waiting on process groups, and the `jobs`,
+//! `fg`, `bg` and `wait` builtins.
+
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus};
+
+use crate::redirect::BuiltinIo;
+use crate::shell::Shell;
+use crate::sys;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessState {
+    Running,
+    /// Stopped by the given signal.
+    Stopped(i32),
+    Exited(i32),
+    /// Killed by the given signal.
+    Signaled(i32),
+}
+
+impl ProcessState {
+    pub fn from_wait(status: ExitStatus) -> Self {
+        if let Some(signal) = status.stopped_signal() {
+            ProcessState::Stopped(signal)
+        } else if status.continued() {
+            ProcessState::Running
+        } else if let Some(signal) = status.signal() {
+            ProcessState::Signaled(signal)
+        } else {
+            ProcessState::Exited(status.code().unwrap_or(0))
+        }
+    }
+
+    /// The status the shell reports for a process in this state.
+    pub fn status(self) -> i32 {
+        match self {
+            ProcessState::Running => 0,
+            ProcessState::Exited(code) => code,
+            ProcessState::Stopped(signal) | ProcessState::Signaled(signal) => 128 + signal,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Process {
+    pub pid: i32,
+    pub state: ProcessState,
+}
+
+/// A pipeline and the process group its processes run in.
+#[derive(Debug)]
+pub struct Job {
+    /// Job number shown as `[1]`; 0 until the job enters the table.
+    pub id: usize,
+    pub pgid: i32,
+    pub processes: Vec<Process>,
+    pub command: String,
+    /// The state changed and the user hasn't been told yet.
+    notify: bool,
+}
+
+impl Job {
+    pub fn new(command: String) -> Self {
+        Self {
+            id: 0,
+            pgid: 0,
+            processes: Vec::new(),
+            command,
+            notify: false,
+        }
+    }
+
+    /// Adds a spawned process. The first one leads the process group.
+    pub fn add_process(&mut self, pid: i32) {
+        if self.pgid == 0 {
+            self.pgid = pid;
+        }
+        self.processes.push(Process {
+            pid,
+            state: ProcessState::Running,
+        });
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.processes
+            .iter()
+            .all(|p| matches!(p.state, ProcessState::Exited(_) | ProcessState::Signaled(_)))
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        !self.is_done()
+            && self
+                .processes
+                .iter()
+                .any(|p| matches!(p.state, ProcessState::Stopped(_)))
+    }
+
+    /// The job's exit status: the status of its last process.
+    pub fn status(&self) -> i32 {
+        if let Some(stopped) = self
+            .processes
+            .iter()
+            .find(|p| matches!(p.state, ProcessState::Stopped(_)))
+        {
+            return stopped.state.status();
+        }
+        self.processes.last().map_or(0, |p| p.state.status())
+    }
+
+    /// The status of each process, in pipeline order.
+    pub fn statuses(&self) -> Vec<i32> {
+        self.processes.iter().map(|p| p.state.status()).collect()
+    }
+
+    fn label(&self) -> String {
+        if self.is_stopped() {
+            return "Stopped".to_string();
+        }
+        if !self.is_done() {
+            return "Running".to_string();
+        }
+        match self.processes.last().map(|p| p.state) {
+            Some(ProcessState::Exited(0)) | None => "Done".to_string(),
+            Some(ProcessState::Exited(code)) => format!("Exit {}", code),
+            Some(ProcessState::Signaled(signal)) => sys::signal_name(signal),
+            Some(_) => "Running".to_string(),
+        }
+    }
+
+    fn update(&mut self, pid: i32, state: ProcessState) -> bool {
+        let Some(process) = self.processes.iter_mut().find(|p| p.pid == pid) else {
+            return false;
+        };
+        process.state = state;
+        if self.is_done() || self.is_stopped() {
+            self.notify = true;
+        }
+        true
+    }
+
+    /// Sends SIGCONT to the whole process group and marks it running again.
+    pub fn resume(&mut self) -> io::Result<()> {
+        for process in &mut self.processes {
+            if let ProcessState::Stopped(_) = process.state {
+                process.state = ProcessState::Running;
+            }
+        }
+        sys::send_signal(-self.pgid, sys::SIGCONT)
+    }
+
+    /// Blocks until every process has finished or one of them got stopped.
+    pub fn wait(&mut self) {
+        for i in 0..self.processes.len() {
+            while self.processes[i].state == ProcessState::Running {
+                match sys::wait_pid(self.processes[i].pid, sys::WUNTRACED) {
+                    Ok(Some((_, status))) => {
+                        self.processes[i].state = ProcessState::from_wait(status)
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    // Already reaped somewhere else; nothing left to wait for
+                    _ => self.processes[i].state = ProcessState::Exited(0),
+                }
+            }
+
+            if let ProcessState::Stopped(_) = self.processes[i].state {
+                // Ctrl+Z stops the whole group: collect the other stop
+                // reports now so they don't show up after the job resumes
+                for process in &mut self.processes[i + 1..] {
+                    if process.state != ProcessState::Running {
+                        continue;
+                    }
+                    let options = sys::WUNTRACED | sys::WNOHANG;
+                    if let Ok(Some((_, status))) = sys::wait_pid(process.pid, options) {
+                        process.state = ProcessState::from_wait(status);
+                    }
+                }
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Job ids, most recently started or stopped last. Decides `%+` and `%-`.
+    recency: Vec<usize>,
+}
+
+impl JobTable {
+    /// Adds a job, keeping its id if it already had one, and makes it the
+    /// current job.
+    pub fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let pos = self.jobs.partition_point(|j| j.id < id);
+        self.jobs.insert(pos, job);
+        self.touch(id);
+        id
+    }
+
+    pub fn take(&mut self, id: usize) -> Option<Job> {
+        let pos = self.jobs.iter().position(|j| j.id == id)?;
+        self.recency.retain(|&other| other != id);
+        Some(self.jobs.remove(pos))
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+
+    fn touch(&mut self, id: usize) {
+        self.recency.retain(|&other| other != id);
+        self.recency.push(id);
+    }
+
+    /// Job ids from most to least current. Like bash, stopped jobs are
+    /// preferred over running ones.
+    fn ranked(&self) -> Vec<usize> {
+        let stopped = |id: &usize| self.jobs.iter().any(|j| j.id == *id && j.is_stopped());
+        let mut ranked: Vec<usize> = self.recency.iter().rev().copied().filter(stopped).collect();
+        ranked.extend(self.recency.iter().rev().filter(|id| !stopped(id)));
+        ranked
+    }
+
+    fn marker(&self, id: usize) -> char {
+        let ranked = self.ranked();
+        if ranked.first() == Some(&id) {
+            '+'
+        } else if ranked.get(1) == Some(&id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// One line of `jobs` output, e.g. `[1]+  Running    sleep 10 &`.
+    pub fn format(&self, job: &Job) -> String {
+        let suffix = if job.is_done() || job.is_stopped() {
+            ""
+        } else {
+            " &"
+        };
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.id,
+            self.marker(job.id),
+            job.label(),
+            job.command,
+            suffix
+        )
+    }
+
+    /// Finds the job named by a jobspec: `%n`, `%+`/`%%`, `%-`, `%prefix`
+    /// or `%?substring`. No spec means the current job.
+    pub fn resolve(&self, spec: Option<&str>) -> Result<usize, String> {
+        let ranked = self.ranked();
+        let found = match spec {
+            None | Some("%") | Some("%%") | Some("%+") => ranked.first().copied(),
+            Some("%-") => ranked.get(1).copied(),
+            Some(spec) => {
+                let name = spec.strip_prefix('%').unwrap_or(spec);
+                if let Ok(id) = name.parse::<usize>() {
+                    self.jobs.iter().find(|j| j.id == id).map(|j| j.id)
+                } else if let Some(needle) = name.strip_prefix('?') {
+                    self.jobs
+                        .iter()
+                        .find(|j| j.command.contains(needle))
+                        .map(|j| j.id)
+                } else {
+                    self.jobs
+                        .iter()
+                        .find(|j| j.command.starts_with(name))
+                        .map(|j| j.id)
+                }
+            }
+        };
+        found.ok_or_else(|| format!("{}: no such job", spec.unwrap_or("current")))
+    }
+
+    /// Collects state changes of background jobs without blocking.
+    pub fn reap(&mut self) {
+        let options = sys::WNOHANG | sys::WUNTRACED | sys::WCONTINUED;
+        while let Ok(Some((pid, status))) = sys::wait_pid(-1, options) {
+            let state = ProcessState::from_wait(status);
+            for job in &mut self.jobs {
+                if job.update(pid, state) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Prints finished and newly stopped jobs, then forgets the finished ones.
+    pub fn notify(&mut self) {
+        let pending: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|j| j.notify)
+            .map(|j| j.id)
+            .collect();
+        for id in pending {
+            if let Some(job) = self.jobs.iter().find(|j| j.id == id) {
+                println!("{}", self.format(job));
+            }
+            if let Some(job) = self.get_mut(id) {
+                job.notify = false;
+                if job.is_done() {
+                    self.take(id);
+                }
+            }
+        }
+    }
+}
+
+/// Sets up an external command to run as part of a job: in the job's process
+/// group (0 starts a new one) and with default signal handling.
+pub fn prepare_command(cmd: &mut Command, shell: &Shell, pgid: i32) {
+    if shell.job_control {
+        cmd.process_group(pgid);
+    }
+    unsafe {
+        cmd.pre_exec(|| {
+            reset_signals();
+            Ok(())
+        });
+    }
+}
+
+/// Puts back the default action of the signals an interactive shell ignores,
+/// which would otherwise stay ignored across exec.
+pub fn reset_signals() {
+    for sig in sys::JOB_CONTROL_SIGNALS {
+        sys::default_signal(sig);
+    }
+}
+
+/// Runs a freshly launched job: waits for it in the foreground, or records it
+/// in the job table when it was started with `&`. Returns the exit status of
+/// each process; a job left in the background just counts as a success.
+pub fn run_job(shell: &mut Shell, job: Job, background: bool) -> Vec<i32> {
+    if background {
+        let pid = job.processes.last().map_or(0, |p| p.pid);
+        shell.last_background_pid = Some(pid);
+        let id = shell.jobs.insert(job);
+        if shell.job_control {
+            println!("[{}] {}", id, pid);
+        }
+        return vec![0];
+    }
+    wait_in_foreground(shell, job)
+}
+
+/// Gives the terminal to the job, waits for it, then takes the terminal back.
+/// A job that gets stopped goes into the job table.
+fn wait_in_foreground(shell: &mut Shell, mut job: Job) -> Vec<i32> {
+    if shell.job_control {
+        let _ = sys::set_foreground(job.pgid);
+    }
+    job.wait();
+    if shell.job_control {
+        let _ = sys::set_foreground(shell.pgid);
+        if let Some(modes) = &shell.tty_modes {
+            let _ = sys::set_termios(0, modes);
+        }
+    }
+
+    // The terminal echoed ^C or similar without a newline; other signals
+    // deserve a word about what happened
+    if let Some(ProcessState::Signaled(signal)) = job.processes.last().map(|p| p.state) {
+        match signal {
+            sys::SIGINT => println!(),
+            sys::SIGPIPE => {}
+            _ => println!("{}", sys::signal_name(signal)),
+        }
+    }
+
+    let statuses = job.statuses();
+    if job.is_stopped() {
+        let id = shell.jobs.insert(job);
+        if let Some(job) = shell.jobs.jobs.iter().find(|j| j.id == id) {
+            println!();
+            println!("{}", shell.jobs.format(job));
+        }
+    }
+    statuses
+}
+
+pub fn builtin_jobs(shell: &mut Shell, args: &[String], io: &mut BuiltinIo) -> i32 {
+    shell.jobs.reap();
+    let pids_only = args.first().is_some_and(|a| a == "-p");
+    for job in &shell.jobs.jobs {
+        if pids_only {
+            io.println(job.pgid);
+        } else {
+            io.println(shell.jobs.format(job));
+        }
+    }
+
+    // Finished jobs have now been reported
+    let done: Vec<usize> = shell
+        .jobs
+        .jobs
+        .iter()
+        .filter(|j| j.is_done())
+        .map(|j| j.id)
+        .collect();
+    for id in done {
+        shell.jobs.take(id);
+    }
+    0
+}
+
+pub fn builtin_fg(shell: &mut Shell, args: &[String], io: &mut BuiltinIo) -> i32 {
+    if !shell.job_control {
+        io.eprintln("fg: no job control");
+        return 1;
+    }
+    let id = match shell.jobs.resolve(args.first().map(String::as_str)) {
+        Ok(id) => id,
+        Err(err) => {
+            io.eprintln(format!("fg: {}", err));
+            return 1;
+        }
+    };
+    let Some(mut job) = shell.jobs.take(id) else {
+        return 1;
+    };
+
+    io.println(&job.command);
+    // Hand over the terminal before waking the job so it can read right away
+    let _ = sys::set_foreground(job.pgid);
+    if let Err(err) = job.resume() {
+        io.eprintln(format!("fg: {}", err));
+    }
+    let statuses = wait_in_foreground(shell, job);
+    statuses.last().copied().unwrap_or(0)
+}
+
+pub fn builtin_bg(shell: &mut Shell, args: &[String], io: &mut BuiltinIo) -> i32 {
+    if !shell.job_control {
+        io.eprintln("bg: no job control");
+        return 1;
+    }
+    let id = match shell.jobs.resolve(args.first().map(String::as_str)) {
+        Ok(id) => id,
+        Err(err) => {
+            io.eprintln(format!("bg: {}", err));
+            return 1;
+        }
+    };
+    let Some(job) = shell.jobs.get_mut(id) else {
+        return 1;
+    };
+    if !job.is_stopped() {
+        io.eprintln(format!("bg: job {} already in background", id));
+        return 0;
+    }
+    if let Err(err) = job.resume() {
+        io.eprintln(format!("bg: {}", err));
+        return 1;
+    }
+    shell.jobs.touch(id);
+    if let Some(job) = shell.jobs.jobs.iter().find(|j| j.id == id) {
+        let marker = shell.jobs.marker(job.id);
+        io.println(format!("[{}]{} {} &", job.id, marker, job.command));
+    }
+    0
+}
+
+/// `wait [%job | pid]...`: with no arguments waits for every background job.
+pub fn builtin_wait(shell: &mut Shell, args: &[String], io: &mut BuiltinIo) -> i32 {
+    let ids: Vec<usize> = if args.is_empty() {
+        shell.jobs.jobs.iter().map(|j| j.id).collect()
+    } else {
+        let mut ids = Vec::new();
+        for arg in args {
+            let found = match arg.parse::<i32>() {
+                Ok(pid) => shell
+                    .jobs
+                    .jobs
+                    .iter()
+                    .find(|j| j.processes.iter().any(|p| p.pid == pid))
+                    .map(|j| j.id)
+                    .ok_or_else(|| format!("pid {} is not a child of this shell", pid)),
+                Err(_) => shell.jobs.resolve(Some(arg)),
+            };
+            match found {
+                Ok(id) => ids.push(id),
+                Err(err) => {
+                    io.eprintln(format!("wait: {}", err));
+                    return 127;
+                }
+            }
+        }
+        ids
+    };
+
+    let mut status = 0;
+    for id in ids {
+        let Some(job) = shell.jobs.get_mut(id) else {
+            continue;
+        };
+        job.wait();
+        status = job.status();
+        if job.is_done() {
+            shell.jobs.take(id);
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(commands: &[&str]) -> JobTable {
+        let mut table = JobTable::default();
+        for command in commands {
+            table.insert(Job::new(command.to_string()));
+        }
+        table
+    }
+
+    #[test]
+    fn resolve_jobspecs() {
+        let table = table(&["sleep 10", "make all", "sleep 20"]);
+        for spec in [None, Some("%"), Some("%%"), Some("%+")] {
+            assert_eq!(table.resolve(spec), Ok(3));
+        }
+        assert_eq!(table.resolve(Some("%-")), Ok(2));
+        assert_eq!(table.resolve(Some("%1")), Ok(1));
+        assert_eq!(table.resolve(Some("2")), Ok(2));
+        assert_eq!(table.resolve(Some("%make")), Ok(2));
+        assert_eq!(table.resolve(Some("%sleep")), Ok(1));
+        assert_eq!(table.resolve(Some("%?20")), Ok(3));
+        assert_eq!(
+            table.resolve(Some("%4")),
+            Err("%4: no such job".to_string())
+        );
+    }
+
+    #[test]
+    fn stopped_jobs_are_current_first() {
+        let mut stopped = Job::new("vim".to_string());
+        stopped.add_process(1);
+        stopped.processes[0].state = ProcessState::Stopped(20);
+
+        let mut table = JobTable::default();
+        table.insert(stopped);
+        table.insert(Job::new("sleep 10".to_string()));
+        assert_eq!(table.resolve(None), Ok(1));
+        assert_eq!(table.resolve(Some("%-")), Ok(2));
+    }
+}
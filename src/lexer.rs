@@ -0,0 +1,1056 @@
+//! Splits raw input into typed tokens.
+//!
+//! Unlike the old `tokenize`, quoting is not thrown away: every word keeps a
+//! list of parts recording how each piece of text was quoted, so later stages
+//! can tell `'a|b'` apart from `a | b`.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::ast::Program;
+use crate::parser::{self, ParseError};
+use crate::variables::is_valid_name;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Plain unquoted text.
+    Literal(String),
+    /// Text between single quotes, taken verbatim.
+    SingleQuoted(String),
+    /// Text between double quotes. Holds `Literal` text, with backslash
+    /// escapes already resolved, and expansions.
+    DoubleQuoted(Vec<WordPart>),
+    /// A single character escaped by a backslash outside of quotes.
+    Escaped(char),
+    /// A parameter expansion such as `$?` or `${HOME}`.
+    Param(ParamExpr),
+    /// `$(...)` or `` `...` ``, already parsed.
+    CommandSubst(Program),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamExpr {
+    /// A variable name, a positional number or a special parameter. In the
+    /// braced form a variable can come with an index, as in `PIPESTATUS[0]`.
+    pub name: String,
+    /// Written as `${name}` rather than `$name`.
+    pub braced: bool,
+    /// What to do with the value, for the `${name<op>...}` forms.
+    pub op: Option<ParamOp>,
+}
+
+/// The operators of `${...}`. `colon` is set for the `:-` style forms, which
+/// treat an empty value like an unset one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamOp {
+    /// `${#name}`
+    Length,
+    /// `${name:-word}`
+    Default { colon: bool, word: Word },
+    /// `${name:=word}`
+    Assign { colon: bool, word: Word },
+    /// `${name:?word}`
+    Error { colon: bool, word: Word },
+    /// `${name:+word}`
+    Alternative { colon: bool, word: Word },
+    /// `${name#pattern}`, or `##` for the longest match
+    RemovePrefix { longest: bool, pattern: Word },
+    /// `${name%pattern}`, or `%%` for the longest match
+    RemoveSuffix { longest: bool, pattern: Word },
+    /// `${name/pattern/replacement}` and its `//`, `/#` and `/%` forms
+    Replace {
+        mode: ReplaceMode,
+        pattern: Word,
+        replacement: Word,
+    },
+    /// `${name:offset:length}`
+    Substring { offset: Word, length: Option<Word> },
+    /// `${name^}`, `${name^^}`, `${name,}` and `${name,,}`
+    Case { upper: bool, all: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaceMode {
+    /// `/`: the first match
+    First,
+    /// `//`: every match
+    All,
+    /// `/#`: a match at the start
+    Prefix,
+    /// `/%`: a match at the end
+    Suffix,
+}
+
+impl fmt::Display for ParamExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.braced {
+            return write!(f, "${}", self.name);
+        }
+        let Some(op) = &self.op else {
+            return write!(f, "${{{}}}", self.name);
+        };
+
+        let colon = |colon: bool| if colon { ":" } else { "" };
+        match op {
+            ParamOp::Length => write!(f, "${{#{}", self.name)?,
+            ParamOp::Default { colon: c, word } => {
+                write!(f, "${{{}{}-{}", self.name, colon(*c), word)?
+            }
+            ParamOp::Assign { colon: c, word } => {
+                write!(f, "${{{}{}={}", self.name, colon(*c), word)?
+            }
+            ParamOp::Error { colon: c, word } => {
+                write!(f, "${{{}{}?{}", self.name, colon(*c), word)?
+            }
+            ParamOp::Alternative { colon: c, word } => {
+                write!(f, "${{{}{}+{}", self.name, colon(*c), word)?
+            }
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let op = if *longest { "##" } else { "#" };
+                write!(f, "${{{}{}{}", self.name, op, pattern)?
+            }
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let op = if *longest { "%%" } else { "%" };
+                write!(f, "${{{}{}{}", self.name, op, pattern)?
+            }
+            ParamOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            } => {
+                let op = match mode {
+                    ReplaceMode::First => "/",
+                    ReplaceMode::All => "//",
+                    ReplaceMode::Prefix => "/#",
+                    ReplaceMode::Suffix => "/%",
+                };
+                write!(f, "${{{}{}{}/{}", self.name, op, pattern, replacement)?
+            }
+            ParamOp::Substring { offset, length } => {
+                write!(f, "${{{}:{}", self.name, offset)?;
+                if let Some(length) = length {
+                    write!(f, ":{}", length)?;
+                }
+            }
+            ParamOp::Case { upper, all } => {
+                let op = match (upper, all) {
+                    (true, true) => "^^",
+                    (true, false) => "^",
+                    (false, true) => ",,",
+                    (false, false) => ",",
+                };
+                write!(f, "${{{}{}", self.name, op)?
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// The text of the word after quote removal, with expansions left as
+    /// written.
+    pub fn unquoted(&self) -> String {
+        let mut out = String::new();
+        push_unquoted(&mut out, &self.parts);
+        out
+    }
+
+    /// Returns the text if the word is made of unquoted text only.
+    pub fn as_literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(s)] => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Splits `NAME=value` into the name and the value. The `=` has to be
+    /// unquoted and the name a valid variable name.
+    pub fn split_assignment(&self) -> Option<(String, Word)> {
+        let Some(WordPart::Literal(first)) = self.parts.first() else {
+            return None;
+        };
+        let (name, rest) = first.split_once('=')?;
+        if !is_valid_name(name) {
+            return None;
+        }
+
+        let mut value = Word::default();
+        if !rest.is_empty() {
+            value.parts.push(WordPart::Literal(rest.to_string()));
+        }
+        value.parts.extend(self.parts[1..].iter().cloned());
+        Some((name.to_string(), value))
+    }
+
+    fn push_literal(&mut self, c: char) {
+        push_literal(&mut self.parts, c);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+}
+
+/// Prints the word back with its original quoting.
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_parts(f, &self.parts, false)
+    }
+}
+
+fn fmt_parts(f: &mut fmt::Formatter<'_>, parts: &[WordPart], in_quotes: bool) -> fmt::Result {
+    for part in parts {
+        match part {
+            WordPart::Literal(s) if in_quotes => {
+                for c in s.chars() {
+                    if matches!(c, '\\' | '"' | '$' | '`') {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+            }
+            WordPart::Literal(s) => write!(f, "{}", s)?,
+            WordPart::SingleQuoted(s) => write!(f, "'{}'", s)?,
+            WordPart::DoubleQuoted(inner) => {
+                write!(f, "\"")?;
+                fmt_parts(f, inner, true)?;
+                write!(f, "\"")?;
+            }
+            WordPart::Escaped(c) => write!(f, "\\{}", c)?,
+            WordPart::Param(param) => write!(f, "{}", param)?,
+            WordPart::CommandSubst(program) => write!(f, "$({})", program)?,
+        }
+    }
+    Ok(())
+}
+
+fn push_unquoted(out: &mut String, parts: &[WordPart]) {
+    for part in parts {
+        match part {
+            WordPart::Literal(s) | WordPart::SingleQuoted(s) => out.push_str(s),
+            WordPart::DoubleQuoted(inner) => push_unquoted(out, inner),
+            WordPart::Escaped(c) => out.push(*c),
+            WordPart::Param(param) => out.push_str(&param.to_string()),
+            WordPart::CommandSubst(program) => out.push_str(&format!("$({})", program)),
+        }
+    }
+}
+
+fn push_literal(parts: &mut Vec<WordPart>, c: char) {
+    if let Some(WordPart::Literal(s)) = parts.last_mut() {
+        s.push(c);
+    } else {
+        parts.push(WordPart::Literal(c.to_string()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Pipe,
+    /// `|&`: a pipe that takes stderr along with stdout.
+    PipeAll,
+    AndIf,
+    OrIf,
+    Semi,
+    Amp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirOp {
+    /// `<`
+    Read,
+    /// `>`, or `>|`
+    Write,
+    /// `>>`
+    Append,
+    /// `<>`
+    ReadWrite,
+    /// `<&`: the target is an fd number, or `-` to close
+    DupInput,
+    /// `>&`: the target is an fd number, or `-` to close
+    DupOutput,
+    /// `&>`: stdout and stderr to the same file
+    WriteAll,
+    /// `&>>`
+    AppendAll,
+    /// `<<` and `<<-`. The target is the body, already read and with tabs
+    /// stripped for `<<-`.
+    HereDoc,
+    /// `<<<`: the target word and a newline
+    HereString,
+}
+
+impl RedirOp {
+    /// The fd the operator applies to when no number is written before it.
+    pub fn default_fd(self) -> u32 {
+        match self {
+            RedirOp::Read
+            | RedirOp::ReadWrite
+            | RedirOp::DupInput
+            | RedirOp::HereDoc
+            | RedirOp::HereString => 0,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for RedirOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            RedirOp::Read => "<",
+            RedirOp::Write => ">",
+            RedirOp::Append => ">>",
+            RedirOp::ReadWrite => "<>",
+            RedirOp::DupInput => "<&",
+            RedirOp::DupOutput => ">&",
+            RedirOp::WriteAll => "&>",
+            RedirOp::AppendAll => "&>>",
+            RedirOp::HereDoc => "<<",
+            RedirOp::HereString => "<<<",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Op(Operator),
+    /// A redirection operator, with the optional fd number written before it.
+    Redirect {
+        fd: Option<u32>,
+        op: RedirOp,
+    },
+    Newline,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word.unquoted()),
+            Token::Op(Operator::Pipe) => write!(f, "|"),
+            Token::Op(Operator::PipeAll) => write!(f, "|&"),
+            Token::Op(Operator::AndIf) => write!(f, "&&"),
+            Token::Op(Operator::OrIf) => write!(f, "||"),
+            Token::Op(Operator::Semi) => write!(f, ";"),
+            Token::Op(Operator::Amp) => write!(f, "&"),
+            Token::Redirect { op, .. } => write!(f, "{}", op),
+            Token::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut lexer = Lexer::new(input);
+    lexer.run()?;
+    Ok(lexer.tokens)
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    tokens: Vec<Token>,
+    current: Word,
+    /// Here-documents whose body starts after the next newline.
+    heredocs: Vec<PendingHereDoc>,
+}
+
+struct PendingHereDoc {
+    /// Index of the body's placeholder word in `tokens`.
+    token: usize,
+    delimiter: String,
+    /// Any part of the delimiter was quoted, so the body is taken verbatim.
+    quoted: bool,
+    /// `<<-`: leading tabs are stripped from every line.
+    strip_tabs: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            tokens: Vec::new(),
+            current: Word::default(),
+            heredocs: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), ParseError> {
+        while let Some(c) = self.chars.next() {
+            match c {
+                ' ' | '\t' => self.finish_word(),
+                '\n' => {
+                    self.finish_word();
+                    self.tokens.push(Token::Newline);
+                    self.read_heredocs()?;
+                }
+                '#' if self.current.is_empty() => {
+                    // Comment: skip to the end of the line
+                    while self.chars.next_if(|&c| c != '\n').is_some() {}
+                }
+                '\'' => self.single_quoted()?,
+                '"' => self.double_quoted()?,
+                '$' => match self.param()? {
+                    Some(param) => self.current.parts.push(param),
+                    None => self.current.push_literal('$'),
+                },
+                '`' => {
+                    let subst = self.backquoted(false)?;
+                    self.current.parts.push(subst);
+                }
+                '\\' => match self.chars.next() {
+                    // A continuation with nothing after it needs another line
+                    Some('\n') if self.chars.peek().is_none() => {
+                        return Err(ParseError::UnexpectedEof);
+                    }
+                    Some('\n') => {} // Line continuation
+                    Some(next_c) => self.current.parts.push(WordPart::Escaped(next_c)),
+                    None => return Err(ParseError::UnexpectedEof),
+                },
+                '|' => {
+                    self.finish_word();
+                    let op = match self.chars.next_if(|&c| matches!(c, '|' | '&')) {
+                        Some('|') => Operator::OrIf,
+                        Some(_) => Operator::PipeAll,
+                        None => Operator::Pipe,
+                    };
+                    self.tokens.push(Token::Op(op));
+                }
+                '&' => {
+                    self.finish_word();
+                    if self.chars.next_if_eq(&'>').is_some() {
+                        let op = if self.chars.next_if_eq(&'>').is_some() {
+                            RedirOp::AppendAll
+                        } else {
+                            RedirOp::WriteAll
+                        };
+                        self.tokens.push(Token::Redirect { fd: None, op });
+                        continue;
+                    }
+                    let op = if self.chars.next_if_eq(&'&').is_some() {
+                        Operator::AndIf
+                    } else {
+                        Operator::Amp
+                    };
+                    self.tokens.push(Token::Op(op));
+                }
+                ';' => {
+                    self.finish_word();
+                    self.tokens.push(Token::Op(Operator::Semi));
+                }
+                '>' => {
+                    let fd = self.take_io_number();
+                    let op = match self.chars.next_if(|&c| matches!(c, '>' | '&' | '|')) {
+                        Some('>') => RedirOp::Append,
+                        Some('&') => RedirOp::DupOutput,
+                        _ => RedirOp::Write,
+                    };
+                    self.tokens.push(Token::Redirect { fd, op });
+                }
+                '<' => {
+                    let fd = self.take_io_number();
+                    let op = match self.chars.next_if(|&c| matches!(c, '>' | '&' | '<')) {
+                        Some('>') => RedirOp::ReadWrite,
+                        Some('&') => RedirOp::DupInput,
+                        Some('<') if self.chars.next_if_eq(&'<').is_some() => RedirOp::HereString,
+                        Some('<') => {
+                            let strip_tabs = self.chars.next_if_eq(&'-').is_some();
+                            self.heredoc(fd, strip_tabs)?;
+                            continue;
+                        }
+                        _ => RedirOp::Read,
+                    };
+                    self.tokens.push(Token::Redirect { fd, op });
+                }
+                _ => self.current.push_literal(c),
+            }
+        }
+        self.finish_word();
+        if !self.heredocs.is_empty() {
+            // The body hasn't been typed yet
+            return Err(ParseError::UnexpectedEof);
+        }
+        Ok(())
+    }
+
+    /// `<<word`, after the operator. The delimiter is only quote-removed,
+    /// never expanded. The body is read at the end of the line, so for now
+    /// an empty word holds its place.
+    fn heredoc(&mut self, fd: Option<u32>, strip_tabs: bool) -> Result<(), ParseError> {
+        while self.chars.next_if(|&c| c == ' ' || c == '\t').is_some() {}
+
+        let mut delimiter = String::new();
+        let mut quoted = false;
+        while let Some(c) = self
+            .chars
+            .next_if(|&c| !matches!(c, ' ' | '\t' | '\n' | ';' | '|' | '&' | '<' | '>'))
+        {
+            match c {
+                '\'' | '"' => {
+                    quoted = true;
+                    loop {
+                        match self.chars.next() {
+                            Some(end) if end == c => break,
+                            Some(c) => delimiter.push(c),
+                            None => return Err(ParseError::UnexpectedEof),
+                        }
+                    }
+                }
+                '\\' => {
+                    quoted = true;
+                    if let Some(c) = self.chars.next() {
+                        delimiter.push(c);
+                    }
+                }
+                c => delimiter.push(c),
+            }
+        }
+        if delimiter.is_empty() && !quoted {
+            return Err(match self.chars.peek() {
+                Some('\n') | None => ParseError::UnexpectedToken("newline".to_string()),
+                Some(c) => ParseError::UnexpectedToken(c.to_string()),
+            });
+        }
+
+        let op = RedirOp::HereDoc;
+        self.tokens.push(Token::Redirect { fd, op });
+        self.tokens.push(Token::Word(Word::default()));
+        self.heredocs.push(PendingHereDoc {
+            token: self.tokens.len() - 1,
+            delimiter,
+            quoted,
+            strip_tabs,
+        });
+        Ok(())
+    }
+
+    /// Reads the bodies of the here-documents started on the line that just
+    /// ended, one after another, each up to its delimiter line.
+    fn read_heredocs(&mut self) -> Result<(), ParseError> {
+        for heredoc in std::mem::take(&mut self.heredocs) {
+            let mut body = String::new();
+            loop {
+                if self.chars.peek().is_none() {
+                    return Err(ParseError::UnexpectedEof);
+                }
+                let mut line: String = self.chars.by_ref().take_while(|&c| c != '\n').collect();
+                if heredoc.strip_tabs {
+                    line = line.trim_start_matches('\t').to_string();
+                }
+                if line == heredoc.delimiter {
+                    break;
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+
+            let part = if heredoc.quoted {
+                WordPart::SingleQuoted(body)
+            } else {
+                WordPart::DoubleQuoted(heredoc_parts(&body)?)
+            };
+            self.tokens[heredoc.token] = Token::Word(Word { parts: vec![part] });
+        }
+        Ok(())
+    }
+
+    fn finish_word(&mut self) {
+        if !self.current.is_empty() {
+            let word = std::mem::take(&mut self.current);
+            self.tokens.push(Token::Word(word));
+        }
+    }
+
+    /// A word made only of digits right before a redirection operator is the
+    /// fd it applies to (`2>`), not an argument.
+    fn take_io_number(&mut self) -> Option<u32> {
+        let fd = self
+            .current
+            .as_literal()
+            .filter(|s| s.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|s| s.parse().ok());
+        if fd.is_some() {
+            self.current = Word::default();
+        } else {
+            self.finish_word();
+        }
+        fd
+    }
+
+    fn single_quoted(&mut self) -> Result<(), ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\'') => break,
+                Some(c) => text.push(c),
+                None => return Err(ParseError::UnexpectedEof),
+            }
+        }
+        self.current.parts.push(WordPart::SingleQuoted(text));
+        Ok(())
+    }
+
+    fn double_quoted(&mut self) -> Result<(), ParseError> {
+        let mut parts = Vec::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => break,
+                Some('\\') => match self.chars.next() {
+                    // Inside double quotes, only specific chars are escaped
+                    Some(c @ ('\\' | '"' | '$' | '`')) => push_literal(&mut parts, c),
+                    Some('\n') => {}
+                    Some(c) => {
+                        push_literal(&mut parts, '\\');
+                        push_literal(&mut parts, c);
+                    }
+                    None => return Err(ParseError::UnexpectedEof),
+                },
+                Some('$') => match self.param()? {
+                    Some(param) => parts.push(param),
+                    None => push_literal(&mut parts, '$'),
+                },
+                Some('`') => parts.push(self.backquoted(true)?),
+                Some(c) => push_literal(&mut parts, c),
+                None => return Err(ParseError::UnexpectedEof),
+            }
+        }
+        self.current.parts.push(WordPart::DoubleQuoted(parts));
+        Ok(())
+    }
+
+    /// Reads the expansion after a `$`. Returns `None` if the `$` doesn't
+    /// start one and should be kept as a literal.
+    fn param(&mut self) -> Result<Option<WordPart>, ParseError> {
+        if self.chars.next_if_eq(&'(').is_some() {
+            return self.command_subst().map(Some);
+        }
+        if self.chars.next_if_eq(&'{').is_some() {
+            return self.braced_param().map(Some);
+        }
+
+        let name = if let Some(c) = self.chars.next_if(|&c| is_special_param(c)) {
+            // Only a single digit: `$10` is `$1` followed by a 0
+            c.to_string()
+        } else if let Some(c) = self.chars.next_if(|&c| c == '_' || c.is_ascii_alphabetic()) {
+            let mut name = c.to_string();
+            while let Some(c) = self
+                .chars
+                .next_if(|&c| c == '_' || c.is_ascii_alphanumeric())
+            {
+                name.push(c);
+            }
+            name
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(WordPart::Param(ParamExpr {
+            name,
+            braced: false,
+            op: None,
+        })))
+    }
+
+    /// `${...}`, after the opening brace.
+    fn braced_param(&mut self) -> Result<WordPart, ParseError> {
+        // `${#name}` is a length, but `${#}` and `${#-x}` are about `$#`
+        let mut ahead = self.chars.clone();
+        if ahead.next() == Some('#') && ahead.peek().is_some_and(|&c| c != '}' && c != '-') {
+            self.chars.next();
+            let name = self.param_name();
+            if name.is_empty() || self.chars.next_if_eq(&'}').is_none() {
+                return Err(self.bad_substitution(&format!("#{}", name)));
+            }
+            return Ok(param(name, Some(ParamOp::Length)));
+        }
+
+        let name = self.param_name();
+        if name.is_empty() {
+            return Err(self.bad_substitution(""));
+        }
+
+        let op = match self.chars.next() {
+            Some('}') => None,
+            None => return Err(ParseError::UnexpectedEof),
+            Some(':') => match self.chars.next_if(|&c| matches!(c, '-' | '=' | '?' | '+')) {
+                Some(c) => Some(self.conditional_op(c, true)?),
+                None => {
+                    let (offset, stop) = self.brace_word(&[':', '}'])?;
+                    let length = match stop {
+                        ':' => Some(self.brace_word(&['}'])?.0),
+                        _ => None,
+                    };
+                    Some(ParamOp::Substring { offset, length })
+                }
+            },
+            Some(c @ ('-' | '=' | '?' | '+')) => Some(self.conditional_op(c, false)?),
+            Some('#') => {
+                let longest = self.chars.next_if_eq(&'#').is_some();
+                let (pattern, _) = self.brace_word(&['}'])?;
+                Some(ParamOp::RemovePrefix { longest, pattern })
+            }
+            Some('%') => {
+                let longest = self.chars.next_if_eq(&'%').is_some();
+                let (pattern, _) = self.brace_word(&['}'])?;
+                Some(ParamOp::RemoveSuffix { longest, pattern })
+            }
+            Some('/') => {
+                let mode = match self.chars.next_if(|&c| matches!(c, '/' | '#' | '%')) {
+                    Some('/') => ReplaceMode::All,
+                    Some('#') => ReplaceMode::Prefix,
+                    Some('%') => ReplaceMode::Suffix,
+                    _ => ReplaceMode::First,
+                };
+                let (pattern, stop) = self.brace_word(&['/', '}'])?;
+                let replacement = match stop {
+                    '/' => self.brace_word(&['}'])?.0,
+                    _ => Word::default(),
+                };
+                Some(ParamOp::Replace {
+                    mode,
+                    pattern,
+                    replacement,
+                })
+            }
+            Some(c @ ('^' | ',')) => {
+                let all = self.chars.next_if_eq(&c).is_some();
+                if self.chars.next_if_eq(&'}').is_none() {
+                    return Err(self.bad_substitution(&name));
+                }
+                Some(ParamOp::Case {
+                    upper: c == '^',
+                    all,
+                })
+            }
+            Some(c) => return Err(self.bad_substitution(&format!("{}{}", name, c))),
+        };
+        Ok(param(name, op))
+    }
+
+    /// The name at the start of `${...}`: a variable, a positional number of
+    /// any length or a special parameter. Empty if there is none. A variable
+    /// keeps its `[index]`, if it has one.
+    fn param_name(&mut self) -> String {
+        if let Some(c) = self
+            .chars
+            .next_if(|&c| is_special_param(c) && !c.is_ascii_digit())
+        {
+            return c.to_string();
+        }
+        let mut name = String::new();
+        if let Some(c) = self.chars.next_if(|c| c.is_ascii_digit()) {
+            name.push(c);
+            while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit()) {
+                name.push(c);
+            }
+        } else if let Some(c) = self.chars.next_if(|&c| c == '_' || c.is_ascii_alphabetic()) {
+            name.push(c);
+            while let Some(c) = self
+                .chars
+                .next_if(|&c| c == '_' || c.is_ascii_alphanumeric())
+            {
+                name.push(c);
+            }
+            let mut ahead = self.chars.clone();
+            if ahead.next() == Some('[') {
+                let index: String = ahead.by_ref().take_while(|&c| c != ']').collect();
+                if !index.is_empty() && !index.contains('}') {
+                    name = format!("{}[{}]", name, index);
+                    self.chars = ahead;
+                }
+            }
+        }
+        name
+    }
+
+    /// `-`, `=`, `?` or `+` and the word after it.
+    fn conditional_op(&mut self, op: char, colon: bool) -> Result<ParamOp, ParseError> {
+        let (word, _) = self.brace_word(&['}'])?;
+        Ok(match op {
+            '-' => ParamOp::Default { colon, word },
+            '=' => ParamOp::Assign { colon, word },
+            '?' => ParamOp::Error { colon, word },
+            _ => ParamOp::Alternative { colon, word },
+        })
+    }
+
+    /// Reads a word inside `${...}` up to one of the unquoted `stops`, which is
+    /// consumed and returned. Quotes and expansions nest as usual.
+    fn brace_word(&mut self, stops: &[char]) -> Result<(Word, char), ParseError> {
+        let outer = std::mem::take(&mut self.current);
+        let stop = self.read_brace_word(stops);
+        let word = std::mem::replace(&mut self.current, outer);
+        stop.map(|stop| (word, stop))
+    }
+
+    fn read_brace_word(&mut self, stops: &[char]) -> Result<char, ParseError> {
+        loop {
+            match self.chars.next() {
+                None => return Err(ParseError::UnexpectedEof),
+                Some(c) if stops.contains(&c) => return Ok(c),
+                Some('\'') => self.single_quoted()?,
+                Some('"') => self.double_quoted()?,
+                Some('$') => match self.param()? {
+                    Some(param) => self.current.parts.push(param),
+                    None => self.current.push_literal('$'),
+                },
+                Some('`') => {
+                    let subst = self.backquoted(false)?;
+                    self.current.parts.push(subst);
+                }
+                Some('\\') => match self.chars.next() {
+                    Some(c) => self.current.parts.push(WordPart::Escaped(c)),
+                    None => return Err(ParseError::UnexpectedEof),
+                },
+                Some(c) => self.current.push_literal(c),
+            }
+        }
+    }
+
+    /// `$(...)`, after the opening parenthesis. Finds the matching `)`,
+    /// stepping over quoted text and nested parentheses, and parses what is
+    /// in between as a program of its own.
+    fn command_subst(&mut self) -> Result<WordPart, ParseError> {
+        let mut source = String::new();
+        let mut depth = 0;
+        loop {
+            let mut c = self.chars.next().ok_or(ParseError::UnexpectedEof)?;
+            match c {
+                ')' if depth == 0 => break,
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                '\\' => {
+                    source.push(c);
+                    c = self.chars.next().ok_or(ParseError::UnexpectedEof)?;
+                }
+                '\'' | '"' | '`' => {
+                    // Copied as is; only the end of the quote matters here
+                    source.push(c);
+                    let quote = c;
+                    loop {
+                        let c = self.chars.next().ok_or(ParseError::UnexpectedEof)?;
+                        source.push(c);
+                        if c == quote {
+                            break;
+                        }
+                        if c == '\\' && quote != '\'' {
+                            source.push(self.chars.next().ok_or(ParseError::UnexpectedEof)?);
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            source.push(c);
+        }
+        subst(&source)
+    }
+
+    /// `` `...` ``, after the opening backquote. In here a backslash only
+    /// escapes `$`, `` ` `` and `\\`, plus `"` when inside double quotes.
+    fn backquoted(&mut self, in_double_quotes: bool) -> Result<WordPart, ParseError> {
+        let mut source = String::new();
+        loop {
+            match self.chars.next() {
+                Some('`') => break,
+                Some('\\') => match self.chars.next() {
+                    Some(c @ ('$' | '`' | '\\')) => source.push(c),
+                    Some('"') if in_double_quotes => source.push('"'),
+                    Some(c) => {
+                        source.push('\\');
+                        source.push(c);
+                    }
+                    None => return Err(ParseError::UnexpectedEof),
+                },
+                Some(c) => source.push(c),
+                None => return Err(ParseError::UnexpectedEof),
+            }
+        }
+        subst(&source)
+    }
+
+    /// Skips the rest of a `${...}` that can't be parsed, for the message.
+    fn bad_substitution(&mut self, start: &str) -> ParseError {
+        let mut text = format!("${{{}", start);
+        for c in self.chars.by_ref() {
+            text.push(c);
+            if c == '}' {
+                break;
+            }
+        }
+        ParseError::BadSubstitution(text)
+    }
+}
+
+fn param(name: String, op: Option<ParamOp>) -> WordPart {
+    WordPart::Param(ParamExpr {
+        name,
+        braced: true,
+        op,
+    })
+}
+
+/// The expansions in the body of a here-document with an unquoted
+/// delimiter. Quotes are plain text in there, and a backslash only escapes
+/// `$`, `` ` ``, `\` and newlines.
+fn heredoc_parts(body: &str) -> Result<Vec<WordPart>, ParseError> {
+    let mut lexer = Lexer::new(body);
+    let mut parts = Vec::new();
+    while let Some(c) = lexer.chars.next() {
+        match c {
+            '\\' => match lexer.chars.next() {
+                Some(c @ ('$' | '`' | '\\')) => push_literal(&mut parts, c),
+                Some('\n') => {}
+                Some(c) => {
+                    push_literal(&mut parts, '\\');
+                    push_literal(&mut parts, c);
+                }
+                None => push_literal(&mut parts, '\\'),
+            },
+            '$' => match lexer.param()? {
+                Some(param) => parts.push(param),
+                None => push_literal(&mut parts, '$'),
+            },
+            '`' => parts.push(lexer.backquoted(false)?),
+            c => push_literal(&mut parts, c),
+        }
+    }
+    Ok(parts)
+}
+
+/// Parses the text of a command substitution. Its end has already been
+/// found, so running out of input inside it is an error, not a reason to
+/// read another line.
+fn subst(source: &str) -> Result<WordPart, ParseError> {
+    match parser::parse(source) {
+        Ok(program) => Ok(WordPart::CommandSubst(program)),
+        Err(ParseError::UnexpectedEof) => Err(ParseError::UnexpectedToken(")".to_string())),
+        Err(err) => Err(err),
+    }
+}
+
+/// Parameters with a one character name: `$?`, `$$`, `$1`, ...
+fn is_special_param(c: char) -> bool {
+    matches!(c, '?' | '#' | '@' | '*' | '$' | '!' | '0'..='9')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<String> {
+        tokenize(input)
+            .unwrap()
+            .iter()
+            .map(|t| t.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn words_and_operators() {
+        assert_eq!(
+            tokens("a && b || c; d > f 2>>g"),
+            ["a", "&&", "b", "||", "c", ";", "d", ">", "f", ">>", "g"]
+        );
+        assert_eq!(
+            tokenize("2>>g").unwrap()[0],
+            Token::Redirect {
+                fd: Some(2),
+                op: RedirOp::Append
+            }
+        );
+    }
+
+    #[test]
+    fn unfinished_quotes_want_more() {
+        assert_eq!(tokenize("echo 'a"), Err(ParseError::UnexpectedEof));
+        assert_eq!(tokenize("echo \"a"), Err(ParseError::UnexpectedEof));
+    }
+
+    #[test]
+    fn quoted_pipe_is_part_of_the_word() {
+        assert_eq!(tokens("echo 'a|b'"), ["echo", "a|b"]);
+        assert_eq!(tokens(r#"grep "x|y""#), ["grep", "x|y"]);
+    }
+
+    #[test]
+    fn escaped_pipe_is_part_of_the_word() {
+        assert_eq!(tokens(r"a\|b"), ["a|b"]);
+    }
+
+    #[test]
+    fn unquoted_pipe_splits_words() {
+        assert_eq!(tokens("a|b"), ["a", "|", "b"]);
+        assert_eq!(tokenize("a|b").unwrap()[1], Token::Op(Operator::Pipe));
+    }
+
+    #[test]
+    fn redirections_keep_their_order() {
+        let dup = Token::Redirect {
+            fd: Some(2),
+            op: RedirOp::DupOutput,
+        };
+        let write = Token::Redirect {
+            fd: None,
+            op: RedirOp::Write,
+        };
+        let first = tokenize("a 2>&1 >f").unwrap();
+        assert_eq!(first[1], dup);
+        assert_eq!(first[3], write);
+        let second = tokenize("a >f 2>&1").unwrap();
+        assert_eq!(second[1], write);
+        assert_eq!(second[3], dup);
+        assert_eq!(tokens("a 2>&1 >f"), ["a", ">&", "1", ">", "f"]);
+    }
+
+    fn literal(s: &str) -> WordPart {
+        WordPart::Literal(s.to_string())
+    }
+
+    fn word(parts: Vec<WordPart>) -> Token {
+        Token::Word(Word { parts })
+    }
+
+    #[test]
+    fn heredoc_body_replaces_the_delimiter() {
+        let heredoc = Token::Redirect {
+            fd: None,
+            op: RedirOp::HereDoc,
+        };
+        let param = WordPart::Param(ParamExpr {
+            name: "x".to_string(),
+            braced: false,
+            op: None,
+        });
+        assert_eq!(
+            tokenize("cat <<EOF\nhi $x\nEOF\n"),
+            Ok(vec![
+                word(vec![literal("cat")]),
+                heredoc.clone(),
+                word(vec![WordPart::DoubleQuoted(vec![
+                    literal("hi "),
+                    param,
+                    literal("\n"),
+                ])]),
+                Token::Newline,
+            ])
+        );
+        assert_eq!(
+            tokenize("cat <<'EOF'\nhi $x\nEOF\n").unwrap()[2],
+            word(vec![WordPart::SingleQuoted("hi $x\n".to_string())])
+        );
+        assert_eq!(
+            tokenize("cat <<-EOF\n\thi\n\tEOF\n").unwrap()[2],
+            word(vec![WordPart::DoubleQuoted(vec![literal("hi\n")])])
+        );
+    }
+
+    #[test]
+    fn unfinished_heredoc_wants_more() {
+        assert_eq!(tokenize("cat <<EOF\nhi\n"), Err(ParseError::UnexpectedEof));
+    }
+}
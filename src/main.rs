@@ -4,11 +4,44 @@ use std::fs::File;
 use std::io::Read;
 #[allow(unused_imports)]
 use std::io::{self, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process::{Command, Stdio};
 
-const SHELL_BUILTINS: &[&str] = &["exit", "echo", "type", "pwd", "cd"];
+mod ast;
+mod brace;
+mod editor;
+mod expand;
+mod glob;
+mod history;
+mod jobs;
+mod lexer;
+mod options;
+mod parser;
+mod pattern;
+mod redirect;
+mod shell;
+mod sys;
+mod terminal;
+mod unicode;
+mod variables;
+
+use ast::{AndOrList, AndOrOp, Assignment, Pipeline, Program, SimpleCommand};
+use editor::{Editor, ReadResult};
+use expand::{ExpandError, expand_assignment, expand_words};
+use history::History;
+use jobs::{Job, ProcessState};
+use parser::ParseError;
+use redirect::{BuiltinIo, RedirectError, Redirections};
+use shell::Shell;
+use variables::Saved;
+
+const SHELL_BUILTINS: &[&str] = &[
+    "exit", "echo", "type", "pwd", "cd", "jobs", "fg", "bg", "wait", "history", "export", "unset",
+    "shopt", "set",
+];
 
 fn is_executable(path: &std::path::Path) -> bool {
     if let Ok(metadata) = fs::metadata(path) {
@@ -18,12 +51,12 @@ fn is_executable(path: &std::path::Path) -> bool {
     false
 }
 
-fn find_in_path(command: &str) -> Option<String> {
-    let Some(path_os) = env::var_os("PATH") else {
-        return None;
-    };
+/// Looks `command` up in the shell's `PATH`, not the one the shell itself
+/// was started with.
+fn find_in_path(command: &str, shell: &Shell) -> Option<String> {
+    let path_var = shell.vars.get("PATH")?;
 
-    for dir in env::split_paths(&path_os) {
+    for dir in env::split_paths(path_var) {
         let candidate = dir.join(command);
         if candidate.exists() && is_executable(&candidate) {
             return Some(candidate.to_string_lossy().into_owned());
@@ -32,425 +65,818 @@ fn find_in_path(command: &str) -> Option<String> {
     None
 }
 
-/// Replaces the manual char loop and .split(' ')
-fn tokenize(input: &str) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut current = String::new();
-    let mut inside_single_quote = false;
-    let mut inside_double_quote = false;
-
-    let mut chars = input.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        match c {
-            '\'' if !inside_double_quote => {
-                inside_single_quote = !inside_single_quote;
-                // Note: We don't push the quote itself to the token
-            }
-            '"' if !inside_single_quote => {
-                inside_double_quote = !inside_double_quote;
-            }
-            '\\' if !inside_single_quote => {
-                if let Some(&next_c) = chars.peek() {
-                    if inside_double_quote {
-                        // Inside double quotes, only specific chars are escaped
-                        if next_c == '\\' || next_c == '"' || next_c == '$' || next_c == '\n' {
-                            current.push(chars.next().unwrap());
-                        } else {
-                            current.push('\\');
-                        }
-                    } else {
-                        // Outside quotes, backslash escapes the very next char
-                        current.push(chars.next().unwrap());
-                    }
-                }
-            }
-            ' ' if !inside_single_quote && !inside_double_quote => {
-                if !current.is_empty() {
-                    tokens.push(current.clone());
-                    current.clear();
-                }
-            }
-            _ => {
-                current.push(c);
-            }
+/// Checks that `command` can be run: names containing a slash are used as a
+/// path directly, anything else is looked up in PATH. On failure, reports the
+/// problem through the command's own redirections and returns the
+/// conventional status (127 not found, 126 not executable).
+fn check_command(command: &str, shell: &Shell, io: &mut BuiltinIo) -> Result<(), i32> {
+    if !command.contains('/') {
+        if find_in_path(command, shell).is_some() {
+            return Ok(());
         }
+        io.eprintln(format!("{}: not found", command));
+        return Err(127);
     }
 
-    if !current.is_empty() {
-        tokens.push(current);
+    let path = Path::new(command);
+    if !path.exists() {
+        io.eprintln(format!("{}: No such file or directory", command));
+        Err(127)
+    } else if path.is_dir() {
+        io.eprintln(format!("{}: Is a directory", command));
+        Err(126)
+    } else if !is_executable(path) {
+        io.eprintln(format!("{}: Permission denied", command));
+        Err(126)
+    } else {
+        Ok(())
     }
-    tokens
+}
+
+/// Gives `cmd` the shell's exported variables as its whole environment, plus
+/// the `NAME=value` prefixes written before the command, which are for this
+/// one child only.
+fn set_environment(
+    cmd: &mut Command,
+    shell: &mut Shell,
+    assignments: &[Assignment],
+) -> Result<(), ExpandError> {
+    cmd.env_clear();
+    cmd.envs(shell.vars.environment());
+    for assignment in assignments {
+        cmd.env(
+            &assignment.name,
+            expand_assignment(&assignment.value, shell)?,
+        );
+    }
+    Ok(())
 }
 
 struct CommandContext {
     argv: Vec<String>,
-    stdout_file: Option<File>,
-    stderr_file: Option<File>,
+    redirections: Redirections,
 }
 
 impl CommandContext {
-    fn parse(tokens: Vec<String>) -> Self {
-        let mut final_argv = Vec::new();
-        let mut stdout_path = None;
-        let mut stderr_path = None;
-        let mut append_stdout = false;
-        let mut append_stderr = false;
-
-        let mut i = 0;
-        while i < tokens.len() {
-            match tokens[i].as_str() {
-                ">" | "1>" => {
-                    stdout_path = tokens.get(i + 1).cloned();
-                    append_stdout = false;
-                    i += 2;
-                }
-                ">>" | "1>>" => {
-                    stdout_path = tokens.get(i + 1).cloned();
-                    append_stdout = true;
-                    i += 2;
-                }
-                "2>" => {
-                    stderr_path = tokens.get(i + 1).cloned();
-                    append_stderr = false;
-                    i += 2;
-                }
-                "2>>" => {
-                    stderr_path = tokens.get(i + 1).cloned();
-                    append_stderr = true;
-                    i += 2;
-                }
-                _ => {
-                    final_argv.push(tokens[i].clone());
-                    i += 1;
-                }
-            }
+    fn new(command: &SimpleCommand, shell: &mut Shell) -> Result<Self, RedirectError> {
+        Ok(Self {
+            argv: expand_argv(command, shell)?,
+            redirections: Redirections::new(&command.redirects, shell)?,
+        })
+    }
+}
+
+/// Expands the command's words. Arguments to `export` that look like
+/// assignments are expanded like assignments, so `export A=$b` is not split.
+fn expand_argv(command: &SimpleCommand, shell: &mut Shell) -> Result<Vec<String>, ExpandError> {
+    if command.words.first().and_then(|w| w.as_literal()) != Some("export") {
+        return expand_words(&command.words, shell);
+    }
+
+    let mut argv = Vec::new();
+    for word in &command.words {
+        if let Some((name, value)) = word.split_assignment() {
+            argv.push(format!("{}={}", name, expand_assignment(&value, shell)?));
+        } else {
+            argv.extend(expand_words(std::slice::from_ref(word), shell)?);
         }
+    }
+    Ok(argv)
+}
 
-        let open_file = |path: String, append: bool| {
-            fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .append(append)
-                .truncate(!append)
-                .open(path)
-                .ok()
-        };
+/// Reports an expansion error. It abandons the command, and a
+/// non-interactive shell exits, as POSIX asks. `failglob` only fails the
+/// command, as it does in bash.
+fn expansion_failed(err: ExpandError, shell: &Shell) -> ExecResult {
+    eprintln!("{}", err);
+    if shell.interactive || matches!(err, ExpandError::NoMatch(_)) {
+        Ok(1)
+    } else {
+        Err(Exit(1))
+    }
+}
+
+/// Returned up the call chain when `exit` runs, carrying the shell's exit code.
+struct Exit(i32);
 
-        Self {
-            argv: final_argv,
-            stdout_file: stdout_path.and_then(|p| open_file(p, append_stdout)),
-            stderr_file: stderr_path.and_then(|p| open_file(p, append_stderr)),
+/// The exit status of whatever was run, unless the shell has to exit.
+type ExecResult = Result<i32, Exit>;
+
+fn execute_program(program: &Program, shell: &mut Shell) -> ExecResult {
+    let mut status = 0;
+    for and_or in &program.items {
+        status = execute_and_or(and_or, shell)?;
+    }
+    Ok(status)
+}
+
+fn execute_and_or(list: &AndOrList, shell: &mut Shell) -> ExecResult {
+    if list.background {
+        return Ok(execute_in_background(list, shell));
+    }
+
+    let mut status = execute_pipeline(&list.first, shell, false)?;
+    shell.last_status = status;
+
+    // `a && b || c`: each step only runs if the status so far allows it
+    for (op, pipeline) in &list.rest {
+        let should_run = match op {
+            AndOrOp::And => status == 0,
+            AndOrOp::Or => status != 0,
+        };
+        if should_run {
+            status = execute_pipeline(pipeline, shell, false)?;
+            shell.last_status = status;
         }
     }
+    Ok(status)
 }
 
-fn execute_command(input: &str) -> bool {
-    let argv = tokenize(input);
-    let ctx = CommandContext::parse(argv);
+/// Starts `list &`. A plain pipeline of external commands is spawned directly
+/// as a job; anything involving builtins or `&&`/`||` needs a subshell.
+fn execute_in_background(list: &AndOrList, shell: &mut Shell) -> i32 {
+    let is_external = |command: &SimpleCommand| {
+        command
+            .words
+            .first()
+            .is_some_and(|w| !SHELL_BUILTINS.contains(&w.unquoted().as_str()))
+    };
 
-    let command = &ctx.argv[0];
-    let args = &ctx.argv[1..];
+    if list.rest.is_empty() && list.first.commands.iter().all(is_external) {
+        // Cannot exit: no builtins get to run
+        return execute_pipeline(&list.first, shell, true).unwrap_or(0);
+    }
+    spawn_subshell(list, shell)
+}
 
-    match command.as_str() {
-        "exit" => {
-            set_raw_mode(false);
-            return false;
+/// Runs `list` as a background job in a forked copy of the shell.
+fn spawn_subshell(list: &AndOrList, shell: &mut Shell) -> i32 {
+    // Anything still buffered would otherwise be printed by both processes
+    let _ = io::stdout().flush();
+
+    match sys::fork_process() {
+        Ok(0) => {
+            if shell.job_control {
+                let _ = sys::set_process_group(0, 0);
+            }
+            shell.job_control = false;
+            shell.interactive = false;
+            jobs::reset_signals();
+
+            let mut list = list.clone();
+            list.background = false;
+            let status = match execute_and_or(&list, shell) {
+                Ok(status) | Err(Exit(status)) => status,
+            };
+            let _ = io::stdout().flush();
+            sys::exit_now(status);
         }
-        "echo" => {
-            let output = args.join(" ");
-            if let Some(mut file) = ctx.stdout_file {
-                writeln!(file, "{}", output).unwrap();
-            } else {
-                println!("{}", output);
+        Ok(pid) => {
+            if shell.job_control {
+                let _ = sys::set_process_group(pid, pid);
             }
+            let mut job = Job::new(list.to_string());
+            job.add_process(pid);
+            jobs::run_job(shell, job, true);
+            0
         }
-        "type" => {
-            let Some(query) = args.get(0) else {
-                return true;
-            };
+        Err(err) => {
+            eprintln!("fork: {}", err);
+            1
+        }
+    }
+}
 
-            let res = if SHELL_BUILTINS.contains(&query.as_str()) {
-                format!("{} is a shell builtin", query)
-            } else if let Some(full_path) = find_in_path(query) {
-                format!("{} is {}", query, full_path)
-            } else {
-                format!("{}: not found", query)
+/// `cd [dir]`, with `cd -` going back to `$OLDPWD`. Keeps `PWD` and
+/// `OLDPWD` up to date.
+fn builtin_cd(shell: &mut Shell, args: &[String], io: &mut BuiltinIo) -> i32 {
+    let target = match args.first().map(String::as_str) {
+        None => match shell.vars.get("HOME") {
+            Some(home) if !home.is_empty() => home.to_string(),
+            _ => {
+                io.eprintln("cd: HOME not set");
+                return 1;
+            }
+        },
+        Some("-") => match shell.vars.get("OLDPWD") {
+            Some(old) => old.to_string(),
+            None => {
+                io.eprintln("cd: OLDPWD not set");
+                return 1;
+            }
+        },
+        Some(dir) => dir.to_string(),
+    };
+
+    let old_dir = match shell.vars.get("PWD") {
+        Some(pwd) => Some(pwd.to_string()),
+        None => env::current_dir()
+            .ok()
+            .map(|dir| dir.to_string_lossy().into_owned()),
+    };
+    if env::set_current_dir(&target).is_err() {
+        io.eprintln(format!("cd: {}: No such file or directory", target));
+        return 1;
+    }
+    if args.first().is_some_and(|a| a == "-") {
+        io.println(&target);
+    }
+
+    let new_dir = env::current_dir()
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .unwrap_or(target);
+    if let Some(old_dir) = old_dir {
+        shell.vars.set("OLDPWD", old_dir);
+    }
+    shell.vars.set("PWD", new_dir);
+    0
+}
+
+/// Runs `run` in a forked copy of the shell whose stdout is a pipe, and
+/// returns what it wrote there along with its exit status. This is how
+/// command substitutions run.
+fn capture_output(shell: &mut Shell, run: impl FnOnce(&mut Shell) -> ExecResult) -> (String, i32) {
+    let (mut reader, writer) = match io::pipe() {
+        Ok(pipe) => pipe,
+        Err(err) => {
+            eprintln!("pipe: {}", sys::error_message(&err));
+            return (String::new(), 1);
+        }
+    };
+    let _ = io::stdout().flush();
+
+    match sys::fork_process() {
+        Ok(0) => {
+            drop(reader);
+            let _ = sys::duplicate_fd(writer.as_raw_fd(), 1);
+            drop(writer);
+            shell.job_control = false;
+            shell.interactive = false;
+            jobs::reset_signals();
+
+            let status = match run(shell) {
+                Ok(status) | Err(Exit(status)) => status,
+            };
+            let _ = io::stdout().flush();
+            sys::exit_now(status);
+        }
+        Ok(pid) => {
+            drop(writer);
+            let mut output = Vec::new();
+            let _ = reader.read_to_end(&mut output);
+            let status = match sys::wait_pid(pid, 0) {
+                Ok(Some((_, status))) => ProcessState::from_wait(status).status(),
+                _ => 1,
             };
+            (String::from_utf8_lossy(&output).into_owned(), status)
+        }
+        Err(err) => {
+            eprintln!("fork: {}", err);
+            (String::new(), 1)
+        }
+    }
+}
 
-            if let Some(mut file) = ctx.stdout_file {
-                writeln!(file, "{}", res).unwrap();
-            } else {
-                println!("{}", res);
+fn execute_command(command: &SimpleCommand, shell: &mut Shell) -> ExecResult {
+    shell.last_substitution_status = None;
+    let ctx = match CommandContext::new(command, shell) {
+        Ok(ctx) => ctx,
+        Err(RedirectError::Expand(err)) => return expansion_failed(err, shell),
+        Err(err) => {
+            eprintln!("{}", err);
+            return Ok(1);
+        }
+    };
+    run_command(command, ctx, shell)
+}
+
+/// Runs a command whose words and redirections have been expanded.
+fn run_command(command: &SimpleCommand, ctx: CommandContext, shell: &mut Shell) -> ExecResult {
+    let source = command.to_string();
+    let assignments = &command.assignments;
+
+    let Some(command) = ctx.argv.first() else {
+        // Only assignments (and redirections): they set shell variables
+        for assignment in assignments {
+            match expand_assignment(&assignment.value, shell) {
+                Ok(value) => shell.vars.set(&assignment.name, value),
+                Err(err) => return expansion_failed(err, shell),
             }
         }
-        "pwd" => {
-            println!("{}", env::current_dir().unwrap().display())
+        // `x=$(false)` fails along with its substitution
+        return Ok(shell.last_substitution_status.take().unwrap_or(0));
+    };
+    let args = &ctx.argv[1..];
+    let mut io = ctx.redirections.builtin_io();
+
+    // `NAME=value` before a builtin only lasts while it runs. External
+    // commands get theirs in their environment instead.
+    let mut saved = Vec::new();
+    if SHELL_BUILTINS.contains(&command.as_str()) {
+        for assignment in assignments {
+            match expand_assignment(&assignment.value, shell) {
+                Ok(value) => saved.push(shell.vars.set_temporarily(&assignment.name, value)),
+                Err(err) => {
+                    restore_variables(shell, saved);
+                    return expansion_failed(err, shell);
+                }
+            }
         }
-        "cd" => {
-            let home_dir = env::var("HOME").unwrap();
-            let path = match args.get(0) {
-                None => PathBuf::from(&home_dir),
-                Some(raw_arg) => {
-                    if let Some(rest) = raw_arg.strip_prefix('~') {
-                        Path::new(&home_dir).join(rest)
-                    } else {
-                        PathBuf::from(raw_arg)
+    }
+
+    let status = match command.as_str() {
+        "exit" => {
+            let code = match args.first() {
+                None => shell.last_status,
+                Some(arg) => match arg.parse::<i64>() {
+                    Ok(n) => (n & 0xff) as i32,
+                    Err(_) => {
+                        io.eprintln(format!("exit: {}: numeric argument required", arg));
+                        2
                     }
-                }
+                },
             };
-
-            if let Err(_) = env::set_current_dir(&path) {
-                let display_path = args.get(0).map(|s| s.as_str()).unwrap_or("~");
-                println!("cd: {}: No such file or directory", display_path);
+            return Err(Exit(code));
+        }
+        "echo" => match writeln!(io.stdout, "{}", args.join(" ")).and_then(|_| io.stdout.flush()) {
+            Ok(()) => 0,
+            Err(err) => {
+                io.eprintln(format!("echo: write error: {}", sys::error_message(&err)));
+                1
+            }
+        },
+        "type" => match args.first() {
+            None => 0,
+            Some(query) => {
+                let (res, status) = if SHELL_BUILTINS.contains(&query.as_str()) {
+                    (format!("{} is a shell builtin", query), 0)
+                } else if let Some(full_path) = find_in_path(query, shell) {
+                    (format!("{} is {}", query, full_path), 0)
+                } else {
+                    (format!("{}: not found", query), 1)
+                };
+
+                io.println(res);
+                status
+            }
+        },
+        "pwd" => {
+            // Still known after the directory itself has been removed
+            let dir = match shell.vars.get("PWD") {
+                Some(pwd) => Ok(pwd.to_string()),
+                None => env::current_dir().map(|dir| dir.display().to_string()),
+            };
+            match dir {
+                Ok(dir) => {
+                    io.println(dir);
+                    0
+                }
+                Err(err) => {
+                    io.eprintln(format!("pwd: {}", sys::error_message(&err)));
+                    1
+                }
             }
         }
+        "cd" => builtin_cd(shell, args, &mut io),
+        "jobs" => jobs::builtin_jobs(shell, args, &mut io),
+        "fg" => jobs::builtin_fg(shell, args, &mut io),
+        "bg" => jobs::builtin_bg(shell, args, &mut io),
+        "wait" => jobs::builtin_wait(shell, args, &mut io),
+        "history" => shell.history.builtin(args, &mut io),
+        "export" => variables::builtin_export(&mut shell.vars, args, &mut io),
+        "unset" => variables::builtin_unset(&mut shell.vars, args, &mut io),
+        "shopt" => options::builtin_shopt(&mut shell.options, args, &mut io),
+        "set" => options::builtin_set(&mut shell.options, args, &mut io),
         _ => {
-            if let Some(_path) = find_in_path(command) {
-                let mut cmd = Command::new(command);
-                cmd.args(args);
+            if let Err(status) = check_command(command, shell, &mut io) {
+                return Ok(status);
+            }
+
+            let mut cmd = Command::new(command);
+            cmd.args(args);
+            if let Err(err) = set_environment(&mut cmd, shell, assignments) {
+                return expansion_failed(err, shell);
+            }
+
+            ctx.redirections.prepare_command(&mut cmd);
+            jobs::prepare_command(&mut cmd, shell, 0);
 
-                if let Some(file) = ctx.stdout_file {
-                    cmd.stdout(file);
+            match cmd.spawn() {
+                Ok(child) => {
+                    let mut job = Job::new(source);
+                    job.add_process(child.id() as i32);
+                    let statuses = jobs::run_job(shell, job, false);
+                    statuses.last().copied().unwrap_or(0)
                 }
-                if let Some(file) = ctx.stderr_file {
-                    cmd.stderr(file);
+                Err(err) => {
+                    eprintln!("{}: {}", command, err);
+                    126
                 }
-
-                cmd.status().unwrap();
-            } else {
-                println!("{}: not found", command);
             }
         }
+    };
+    restore_variables(shell, saved);
+    Ok(status)
+}
+
+/// Puts back variables changed by `set_temporarily`, last change first.
+fn restore_variables(shell: &mut Shell, saved: Vec<Saved>) {
+    for saved in saved.into_iter().rev() {
+        shell.vars.restore(saved);
     }
-    true
 }
 
-fn execute_pipeline(input: &str) -> bool {
-    // Check for pipes
-    if !input.contains('|') {
-        return execute_command(input);
+fn execute_pipeline(pipeline: &Pipeline, shell: &mut Shell, background: bool) -> ExecResult {
+    let statuses = match pipeline.commands.as_slice() {
+        [command] if !background => vec![execute_command(command, shell)?],
+        _ => run_stages(pipeline, shell, background),
+    };
+
+    // The last command decides, or with pipefail the last one that failed
+    let status = if shell.options.pipefail {
+        statuses.iter().rev().find(|&&s| s != 0).copied()
+    } else {
+        statuses.last().copied()
+    };
+    let status = status.unwrap_or(0);
+    shell.pipe_status = statuses;
+
+    if pipeline.negated && !background {
+        return Ok(if status == 0 { 1 } else { 0 });
     }
+    Ok(status)
+}
 
-    // Split into segments
-    let segments: Vec<&str> = input.split('|').map(|s| s.trim()).collect();
-    let mut prev_stdout: Option<Stdio> = None;
-    let mut children = Vec::new();
+/// Starts every command of a pipeline, connected by pipes, and waits for
+/// them unless it runs in the `background`. Returns each one's status.
+fn run_stages(pipeline: &Pipeline, shell: &mut Shell, background: bool) -> Vec<i32> {
+    let segments = &pipeline.commands;
+    let mut prev_stdout: Option<OwnedFd> = None;
+    let mut job = Job::new(pipeline.to_string());
+    // The status of each stage that didn't start; `None` for the ones that
+    // did, which get theirs from the job
+    let mut stages = Vec::new();
 
     // For a multiple-pipe: A | B | ... | N
     for (i, segment) in segments.iter().enumerate() {
         let is_last = i == segments.len() - 1;
-        let ctx = CommandContext::parse(tokenize(segment));
+        let ctx = match CommandContext::new(segment, shell) {
+            Ok(ctx) => ctx,
+            Err(err) => {
+                // The stage fails, the rest of the pipeline still runs
+                eprintln!("{}", err);
+                stages.push(Some(1));
+                if !is_last {
+                    prev_stdout = null_input();
+                }
+                continue;
+            }
+        };
+
+        if ctx.argv.is_empty() {
+            // Expanded to nothing: a stage that reads and writes nothing
+            stages.push(Some(0));
+            if !is_last {
+                prev_stdout = null_input();
+            }
+            continue;
+        }
 
         if SHELL_BUILTINS.contains(&ctx.argv[0].as_str()) {
-            let output = run_builtin_capture(&ctx);
-            if is_last {
-                print!("{}", output);
+            let pipe = if is_last {
+                None
             } else {
-                // Bridge builtin output to next command via a small helper
-                prev_stdout = Some(string_to_stdio(output));
+                match io::pipe() {
+                    Ok(pipe) => Some(pipe),
+                    Err(err) => {
+                        eprintln!("pipe: {}", sys::error_message(&err));
+                        stages.push(Some(1));
+                        prev_stdout = null_input();
+                        continue;
+                    }
+                }
+            };
+            let (reader, writer) = pipe
+                .map(|(r, w)| (OwnedFd::from(r), OwnedFd::from(w)))
+                .unzip();
+            let stdin = prev_stdout.take();
+            // Like every stage, the builtin runs apart from the shell itself
+            let forked = fork_stage(shell, job.pgid, stdin, writer, reader.as_ref(), |shell| {
+                run_command(segment, ctx, shell)
+            });
+            match forked {
+                Ok(pid) => {
+                    job.add_process(pid);
+                    stages.push(None);
+                }
+                Err(err) => {
+                    eprintln!("fork: {}", err);
+                    stages.push(Some(1));
+                }
             }
+            prev_stdout = reader;
         } else {
             let mut cmd = Command::new(&ctx.argv[0]);
             cmd.args(&ctx.argv[1..]);
+            if let Err(err) = set_environment(&mut cmd, shell, &segment.assignments) {
+                eprintln!("{}", err);
+                stages.push(Some(1));
+                if !is_last {
+                    prev_stdout = null_input();
+                }
+                continue;
+            }
 
             // Connect plumbing
             if let Some(prev) = prev_stdout.take() {
-                cmd.stdin(prev);
+                cmd.stdin(Stdio::from(prev));
             }
             if !is_last {
                 cmd.stdout(Stdio::piped());
             }
-
-            let mut child = cmd.spawn().expect("Failed to spawn");
+            ctx.redirections.prepare_command(&mut cmd);
+            // The first process leads a new group the others join
+            jobs::prepare_command(&mut cmd, shell, job.pgid);
+
+            let mut child = match cmd.spawn() {
+                Ok(child) => child,
+                Err(_) => {
+                    // Still run the rest of the pipeline, like other shells do
+                    let mut io = ctx.redirections.builtin_io();
+                    let status = check_command(&ctx.argv[0], shell, &mut io)
+                        .err()
+                        .unwrap_or(126);
+                    stages.push(Some(status));
+                    if !is_last {
+                        prev_stdout = null_input();
+                    }
+                    continue;
+                }
+            };
 
             if !is_last {
-                prev_stdout = child.stdout.take().map(Stdio::from);
+                prev_stdout = child.stdout.take().map(OwnedFd::from);
             }
-            children.push(child);
+            job.add_process(child.id() as i32);
+            stages.push(None);
         }
     }
 
-    // Wait for all external processes to finish
-    for mut child in children {
-        let _ = child.wait();
+    if job.processes.is_empty() {
+        return stages.into_iter().map(|s| s.unwrap_or(0)).collect();
+    }
+    let started = jobs::run_job(shell, job, background);
+    if background {
+        return started;
     }
-    true
+    let mut started = started.into_iter();
+    stages
+        .into_iter()
+        .map(|s| s.or_else(|| started.next()).unwrap_or(0))
+        .collect()
 }
 
-// Helper to turn a String into a Stdio source (for builtins in the middle of pipes)
-fn string_to_stdio(input: String) -> Stdio {
-    let mut child = Command::new("printf")
-        .arg(input)
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
-    Stdio::from(child.stdout.take().unwrap())
+/// Input for the stage after one that couldn't start: nothing at all.
+fn null_input() -> Option<OwnedFd> {
+    File::open("/dev/null").ok().map(OwnedFd::from)
 }
 
-fn run_builtin_capture(ctx: &CommandContext) -> String {
-    match ctx.argv[0].as_str() {
-        "echo" => ctx.argv[1..].join(" ") + "\n",
-        "pwd" => env::current_dir().unwrap().display().to_string() + "\n",
-        "type" => {
-            let query = &ctx.argv[1];
-            if SHELL_BUILTINS.contains(&query.as_str()) {
-                format!("{} is a shell builtin\n", query)
-            } else if let Some(path) = find_in_path(query) {
-                format!("{} is {}\n", query, path)
-            } else {
-                format!("{}: not found\n", query)
+/// Starts a pipeline stage that runs inside the shell, such as a builtin, in
+/// a forked copy of it. `stdin` and `stdout` replace fds 0 and 1 when given.
+/// `unused` is this process's end of the pipe the stage writes into, which
+/// the copy must not hold open. Returns the copy's pid.
+fn fork_stage(
+    shell: &mut Shell,
+    pgid: i32,
+    stdin: Option<OwnedFd>,
+    stdout: Option<OwnedFd>,
+    unused: Option<&OwnedFd>,
+    run: impl FnOnce(&mut Shell) -> ExecResult,
+) -> io::Result<i32> {
+    let _ = io::stdout().flush();
+    let pid = sys::fork_process()?;
+    if pid == 0 {
+        if shell.job_control {
+            let _ = sys::set_process_group(0, pgid);
+        }
+        if let Some(fd) = unused {
+            let _ = sys::close_fd(fd.as_raw_fd());
+        }
+        for (fd, target) in [(stdin, 0), (stdout, 1)] {
+            if let Some(fd) = fd {
+                let _ = sys::duplicate_fd(fd.as_raw_fd(), target);
             }
         }
-        _ => String::new(),
+        shell.job_control = false;
+        shell.interactive = false;
+        jobs::reset_signals();
+        // Quietly stop when the next stage stops reading, as a command would
+        sys::default_signal(sys::SIGPIPE);
+
+        let status = match run(shell) {
+            Ok(status) | Err(Exit(status)) => status,
+        };
+        let _ = io::stdout().flush();
+        sys::exit_now(status);
     }
+    // Also set here, so the group exists before the next stage joins it
+    if shell.job_control {
+        let _ = sys::set_process_group(pid, if pgid == 0 { pid } else { pgid });
+    }
+    Ok(pid)
 }
 
-fn set_raw_mode(enable: bool) {
-    let state = if enable { "raw" } else { "-raw" };
-    let echo = if enable { "-echo" } else { "echo" };
-    Command::new("stty").arg(state).arg(echo).status().ok();
-}
-
-fn handle_autocomplete(buffer: &mut String, tab_count: u32) {
-    let mut matches = Vec::new();
+/// Reads stdin one byte at a time, so commands started from a piped script
+/// only see the input the shell hasn't consumed itself.
+struct UnbufferedStdin;
 
-    // Check Builtins
-    for builtin in SHELL_BUILTINS {
-        if builtin.starts_with(buffer.as_str()) {
-            matches.push(builtin.to_string());
-        }
+impl Read for UnbufferedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Not io::stdin(), which reads ahead into its own buffer
+        let mut stdin = ManuallyDrop::new(unsafe { File::from_raw_fd(0) });
+        let len = buf.len().min(1);
+        stdin.read(&mut buf[..len])
     }
+}
 
-    // Check PATH
-    if let Some(path_var) = env::var_os("PATH") {
-        for dir in env::split_paths(&path_var) {
-            if let Ok(entries) = fs::read_dir(dir) {
-                for entry in entries.flatten() {
-                    let name = entry.file_name().to_string_lossy().into_owned();
-                    if name.starts_with(buffer.as_str()) && is_executable(&entry.path()) {
-                        if !matches.contains(&name) {
-                            matches.push(name);
-                        }
-                    }
+/// Reads up to and including the next newline. `None` at end of input.
+fn read_line(input: &mut dyn Read) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => {
+                line.push(byte[0]);
+                if byte[0] == b'\n' {
+                    break;
                 }
             }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
         }
     }
 
-    matches.sort();
-
-    match matches.len() {
-        0 => {
-            // No match: ring the bell
-            print!("\x07");
-            io::stdout().flush().unwrap();
-        }
-        1 => {
-            // Single match: complete it
-            let completion = &matches[0][buffer.len()..];
-            print!("{} ", completion);
-            buffer.push_str(completion);
-            buffer.push(' ');
-            io::stdout().flush().unwrap();
-        }
-        _ => {
-            // Multiple matches logic
-            handle_multiple_matches(buffer, matches, tab_count);
-        }
+    if line.is_empty() {
+        return Ok(None);
     }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
 }
 
-fn handle_multiple_matches(buffer: &mut String, matches: Vec<String>, tab_count: u32) {
-    if tab_count == 1 {
-        // Longest Common Prefix (LCP) Logic
-        let first = &matches[0];
-        let mut lcp_len = buffer.len();
-
-        'outer: for i in buffer.len()..first.len() {
-            let char_at_i = first.chars().nth(i).unwrap();
-            for m in &matches {
-                if m.chars().nth(i) != Some(char_at_i) {
-                    break 'outer;
+/// Runs commands from a script, a `-c` string or piped stdin: no prompt and
+/// no line editor. Lines are read until they form a complete command, so
+/// quotes and `&&`/`|` can continue onto the next line. Returns the exit code.
+fn run_script(shell: &mut Shell, input: &mut dyn Read) -> i32 {
+    let mut pending = String::new();
+
+    loop {
+        let line = match read_line(input) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        };
+        pending.push_str(&line);
+
+        match parser::parse(&pending) {
+            Ok(program) => {
+                pending.clear();
+                if let Err(Exit(code)) = execute_program(&program, shell) {
+                    return code;
                 }
             }
-            lcp_len += 1;
+            Err(ParseError::UnexpectedEof) => {}
+            Err(err) => {
+                eprintln!("{}: {}", shell.script_name, err);
+                return 2;
+            }
         }
+    }
 
-        if lcp_len > buffer.len() {
-            let extra = &first[buffer.len()..lcp_len];
-            print!("{}", extra);
-            buffer.push_str(extra);
-        } else {
-            print!("\x07"); // Bell if no more common chars
-        }
-    } else if tab_count >= 2 {
-        // Double Tab Listing Logic
-        println!(); // New line for the list
-        println!("\r{}\r", matches.join("  "));
-        print!("$ {}", buffer); // Restore the prompt line
+    if !pending.is_empty() {
+        eprintln!("{}: {}", shell.script_name, ParseError::UnexpectedEof);
+        return 2;
     }
-    let _ = io::stdout().flush();
+    shell.last_status
 }
 
 fn main() {
-    let mut input_buffer = String::new();
-    let mut tab_count = 0;
-
-    loop {
-        print!("$ ");
-        io::stdout().flush().unwrap();
-        input_buffer.clear();
+    let mut shell = Shell::new();
+    let mut args = env::args();
+    shell.script_name = args.next().unwrap_or_default();
+
+    let status = match args.next() {
+        Some(flag) if flag == "-c" => {
+            let Some(command) = args.next() else {
+                eprintln!("{}: -c: option requires an argument", shell.script_name);
+                std::process::exit(2);
+            };
+            // Like sh -c, the next argument is $0 and the rest are $1...
+            if let Some(name) = args.next() {
+                shell.script_name = name;
+            }
+            shell.positional = args.collect();
+            run_script(&mut shell, &mut io::Cursor::new(command))
+        }
+        Some(path) => {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(err) => {
+                    let reason = sys::error_message(&err);
+                    eprintln!("{}: {}: {}", shell.script_name, path, reason);
+                    std::process::exit(127);
+                }
+            };
+            shell.script_name = path;
+            shell.positional = args.collect();
+            run_script(&mut shell, &mut io::BufReader::new(file))
+        }
+        None if sys::is_tty(0) => run_interactive(&mut shell),
+        None => run_script(&mut shell, &mut UnbufferedStdin),
+    };
 
-        // Switch to raw mode to intercept Tab
-        set_raw_mode(true);
+    let _ = io::stdout().flush();
+    std::process::exit(status);
+}
 
-        loop {
-            let mut buffer = [0; 1];
-            io::stdin().read_exact(&mut buffer).unwrap();
-            let c = buffer[0] as char;
+/// The prompt and line editor loop. Returns the exit code.
+fn run_interactive(shell: &mut Shell) -> i32 {
+    let mut editor = Editor::default();
 
-            if c != '\t' {
-                tab_count = 0;
+    // Take our own process group and the terminal, so jobs can be moved in
+    // and out of the foreground. Terminal signals are only meant for them.
+    shell.interactive = true;
+    shell.job_control = true;
+    for sig in sys::JOB_CONTROL_SIGNALS {
+        sys::ignore_signal(sig);
+    }
+    let _ = sys::set_process_group(0, 0);
+    shell.pgid = sys::process_group();
+    let _ = sys::set_foreground(shell.pgid);
+    shell.tty_modes = sys::get_termios(0).ok();
+    shell.history = History::load(&shell.vars);
+
+    let status = loop {
+        // Report background jobs that finished or stopped
+        shell.jobs.reap();
+        shell.jobs.notify();
+
+        let mut input = match editor.read_line("$ ", shell) {
+            ReadResult::Line(line) => line,
+            ReadResult::Interrupted => {
+                shell.last_status = 130;
+                continue;
+            }
+            ReadResult::Eof => {
+                println!("exit");
+                break shell.last_status;
             }
+        };
 
-            match c {
-                '\r' | '\n' => {
-                    // Enter key pressed
-                    set_raw_mode(false); // Back to normal to print output
-                    println!();
-                    if !input_buffer.is_empty() {
-                        if !execute_pipeline(input_buffer.trim()) {
-                            std::process::exit(0);
-                        }
-                    }
-                    break; // Exit inner loop to show new prompt
-                }
-                '\t' => {
-                    // TAB logic
-                    tab_count += 1;
-                    handle_autocomplete(&mut input_buffer, tab_count);
-                }
-                '\x7f' => {
-                    // Backspace logic
-                    if !input_buffer.is_empty() {
-                        input_buffer.pop();
-                        print!("\x08 \x08"); // Move back, overwrite with space, move back
-                        io::stdout().flush().unwrap();
-                    }
+        // An open quote, a trailing `|` or a here-document body still to
+        // come: keep reading lines under the continuation prompt
+        let mut parsed = parser::parse(&input);
+        let mut interrupted = false;
+        while parsed == Err(ParseError::UnexpectedEof) {
+            let prompt = shell.vars.get("PS2").unwrap_or("> ").to_string();
+            match editor.read_line(&prompt, shell) {
+                ReadResult::Line(line) => {
+                    input.push('\n');
+                    input.push_str(&line);
+                    parsed = parser::parse(&input);
                 }
-                '\x03' => {
-                    // Ctrl+C
-                    set_raw_mode(false);
-                    std::process::exit(0);
+                ReadResult::Interrupted => {
+                    shell.last_status = 130;
+                    interrupted = true;
+                    break;
                 }
-                _ => {
-                    // Normal character
-                    input_buffer.push(c);
-                    print!("{}", c);
-                    io::stdout().flush().unwrap();
+                // Give up on the command, but not on the shell
+                ReadResult::Eof => break,
+            }
+        }
+        // The whole command is one entry, here-document body and all
+        if !interrupted {
+            shell.history.add(&input);
+        }
+
+        match parsed {
+            Ok(program) => {
+                if let Err(Exit(code)) = execute_program(&program, shell) {
+                    break code;
                 }
             }
+            Err(_) if interrupted => {}
+            Err(err) => {
+                eprintln!("{}", err);
+                shell.last_status = 2;
+            }
         }
+    };
+
+    if let Err(err) = shell.history.save() {
+        eprintln!("history: {}", sys::error_message(&err));
     }
+    status
 }
@@ -0,0 +1,161 @@
+//! Shell options, turned on and off with `shopt` and `set -o`.
+
+use crate::redirect::BuiltinIo;
+
+#[derive(Default)]
+pub struct Options {
+    /// Patterns that match no files expand to nothing.
+    pub nullglob: bool,
+    /// Patterns that match no files fail the command.
+    pub failglob: bool,
+    /// `*` and friends match names starting with a dot too.
+    pub dotglob: bool,
+    /// `**` matches any number of directories.
+    pub globstar: bool,
+    /// A pipeline fails if any of its commands does, not just the last.
+    pub pipefail: bool,
+}
+
+/// Every option, in the order `shopt` lists them.
+const NAMES: &[&str] = &["dotglob", "failglob", "globstar", "nullglob"];
+
+/// The options `set -o` knows instead, in the order it lists them.
+const SET_NAMES: &[&str] = &["pipefail"];
+
+impl Options {
+    fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "dotglob" => Some(self.dotglob),
+            "failglob" => Some(self.failglob),
+            "globstar" => Some(self.globstar),
+            "nullglob" => Some(self.nullglob),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, name: &str, on: bool) {
+        match name {
+            "dotglob" => self.dotglob = on,
+            "failglob" => self.failglob = on,
+            "globstar" => self.globstar = on,
+            "nullglob" => self.nullglob = on,
+            _ => {}
+        }
+    }
+
+    fn set_option(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "pipefail" => Some(&mut self.pipefail),
+            _ => None,
+        }
+    }
+}
+
+/// `shopt [-pqsu] [NAME...]`
+pub fn builtin_shopt(options: &mut Options, args: &[String], io: &mut BuiltinIo) -> i32 {
+    let mut set = None;
+    let mut print_commands = false;
+    let mut quiet = false;
+    let mut names = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-s" => set = Some(true),
+            "-u" => set = Some(false),
+            "-p" => print_commands = true,
+            "-q" => quiet = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                io.eprintln(format!("shopt: {}: invalid option", flag));
+                io.eprintln("shopt: usage: shopt [-pqsu] [optname ...]");
+                return 2;
+            }
+            name => names.push(name),
+        }
+    }
+
+    let mut status = 0;
+    let named = !names.is_empty();
+    names.retain(|name| {
+        let valid = options.get(name).is_some();
+        if !valid {
+            io.eprintln(format!("shopt: {}: invalid shell option name", name));
+            status = 1;
+        }
+        valid
+    });
+
+    if let Some(on) = set
+        && named
+    {
+        for name in names {
+            options.set(name, on);
+        }
+        return status;
+    }
+
+    // `-s` and `-u` alone list the options that are on or off
+    if !named {
+        names = NAMES
+            .iter()
+            .copied()
+            .filter(|name| set.is_none_or(|on| options.get(name) == Some(on)))
+            .collect();
+    }
+    for name in names {
+        let on = options.get(name) == Some(true);
+        // Asking about an option that is off fails
+        if named && !on {
+            status = 1;
+        }
+        if quiet {
+            continue;
+        }
+        if print_commands {
+            io.println(format!("shopt {} {}", if on { "-s" } else { "-u" }, name));
+        } else {
+            io.println(format!("{:<15}\t{}", name, if on { "on" } else { "off" }));
+        }
+    }
+    status
+}
+
+/// `set [-o NAME] [+o NAME]`. Only options are supported, not the
+/// single-letter flags or setting positional parameters.
+pub fn builtin_set(options: &mut Options, args: &[String], io: &mut BuiltinIo) -> i32 {
+    let mut status = 0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let on = match arg.as_str() {
+            "-o" => true,
+            "+o" => false,
+            flag => {
+                io.eprintln(format!("set: {}: invalid option", flag));
+                io.eprintln("set: usage: set [-o option-name] [+o option-name]");
+                return 2;
+            }
+        };
+        let Some(name) = args.next() else {
+            // `-o` alone lists the options, `+o` as commands that set them
+            for name in SET_NAMES {
+                let value = options.set_option(name).is_some_and(|v| *v);
+                if on {
+                    io.println(format!(
+                        "{:<15}\t{}",
+                        name,
+                        if value { "on" } else { "off" }
+                    ));
+                } else {
+                    io.println(format!("set {} {}", if value { "-o" } else { "+o" }, name));
+                }
+            }
+            continue;
+        };
+        match options.set_option(name) {
+            Some(value) => *value = on,
+            None => {
+                io.eprintln(format!("set: {}: invalid option name", name));
+                status = 2;
+            }
+        }
+    }
+    status
+}
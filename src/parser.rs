@@ -0,0 +1,287 @@
+//! Recursive-descent parser building an `ast::Program` out of lexer tokens.
+//!
+//! Grammar:
+//!   program  := newline* (and_or ((';' | '&' | newline) newline*)?)*
+//!   and_or   := pipeline (('&&' | '||') newline* pipeline)*
+//!   pipeline := '!'* command (('|' | '|&') newline* command)*
+//!   command  := (ASSIGNMENT | redirect)* (WORD | redirect)*
+//!               (at least one of anything)
+//!   redirect := REDIRECT WORD
+
+use thiserror::Error;
+
+use crate::ast::{AndOrList, AndOrOp, Assignment, Pipeline, Program, Redirect, SimpleCommand};
+use crate::lexer::{self, Operator, RedirOp, Token, Word, WordPart};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseError {
+    /// The input stopped in the middle of a construct (open quote, trailing
+    /// `|`, ...). More input could still make it valid.
+    #[error("syntax error: unexpected end of file")]
+    UnexpectedEof,
+    #[error("syntax error near unexpected token `{0}'")]
+    UnexpectedToken(String),
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+}
+
+pub fn parse(input: &str) -> Result<Program, ParseError> {
+    let tokens = lexer::tokenize(input)?;
+    Parser { tokens, pos: 0 }.program()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_op(&mut self, op: Operator) -> bool {
+        if self.peek() == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken(token.to_string()),
+            None => ParseError::UnexpectedToken("newline".to_string()),
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, ParseError> {
+        let mut program = Program::default();
+        self.skip_newlines();
+
+        while self.peek().is_some() {
+            let mut and_or = self.and_or()?;
+            if self.eat_op(Operator::Amp) {
+                and_or.background = true;
+                program.items.push(and_or);
+                self.skip_newlines();
+                continue;
+            }
+
+            program.items.push(and_or);
+            match self.peek() {
+                None => break,
+                Some(Token::Newline) | Some(Token::Op(Operator::Semi)) => {
+                    self.pos += 1;
+                    self.skip_newlines();
+                }
+                Some(_) => return Err(self.unexpected()),
+            }
+        }
+        Ok(program)
+    }
+
+    fn and_or(&mut self) -> Result<AndOrList, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let op = if self.eat_op(Operator::AndIf) {
+                AndOrOp::And
+            } else if self.eat_op(Operator::OrIf) {
+                AndOrOp::Or
+            } else {
+                break;
+            };
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(ParseError::UnexpectedEof);
+            }
+            rest.push((op, self.pipeline()?));
+        }
+        Ok(AndOrList {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        // `! a` inverts, `! ! a` doesn't
+        let mut negated = false;
+        while let Some(Token::Word(word)) = self.peek()
+            && word.parts == [WordPart::Literal("!".to_string())]
+        {
+            self.pos += 1;
+            negated = !negated;
+        }
+
+        let mut commands = vec![self.command()?];
+        loop {
+            match self.peek() {
+                Some(Token::Op(Operator::Pipe)) => {}
+                Some(Token::Op(Operator::PipeAll)) => {
+                    // `a |& b` is `a 2>&1 | b`, after a's own redirections
+                    let last = commands.last_mut().expect("pipeline has a command");
+                    last.redirects.push(Redirect {
+                        fd: Some(2),
+                        op: RedirOp::DupOutput,
+                        target: Word {
+                            parts: vec![WordPart::Literal("1".to_string())],
+                        },
+                    });
+                }
+                _ => break,
+            }
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(ParseError::UnexpectedEof);
+            }
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+
+        loop {
+            match self.peek() {
+                Some(Token::Word(_)) => {
+                    if let Some(Token::Word(word)) = self.next() {
+                        // `NAME=value` only counts before the command name
+                        match word.split_assignment() {
+                            Some((name, value)) if command.words.is_empty() => {
+                                command.assignments.push(Assignment { name, value });
+                            }
+                            _ => command.words.push(word),
+                        }
+                    }
+                }
+                Some(&Token::Redirect { fd, op }) => {
+                    self.pos += 1;
+                    let Some(Token::Word(target)) = self.peek().cloned() else {
+                        return Err(self.unexpected());
+                    };
+                    self.pos += 1;
+                    command.redirects.push(Redirect { fd, op, target });
+                }
+                _ => break,
+            }
+        }
+
+        if command.assignments.is_empty()
+            && command.words.is_empty()
+            && command.redirects.is_empty()
+        {
+            return Err(self.unexpected());
+        }
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &SimpleCommand) -> Vec<String> {
+        command.words.iter().map(|w| w.unquoted()).collect()
+    }
+
+    #[test]
+    fn lists_and_pipelines() {
+        let program = parse("a | b && c || d; e").unwrap();
+        assert_eq!(program.items.len(), 2);
+
+        let list = &program.items[0];
+        assert_eq!(list.first.commands.len(), 2);
+        let ops: Vec<AndOrOp> = list.rest.iter().map(|(op, _)| *op).collect();
+        assert_eq!(ops, [AndOrOp::And, AndOrOp::Or]);
+        assert_eq!(words(&program.items[1].first.commands[0]), ["e"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("a |"), Err(ParseError::UnexpectedEof));
+        assert_eq!(parse("a &&"), Err(ParseError::UnexpectedEof));
+        assert_eq!(
+            parse("| a"),
+            Err(ParseError::UnexpectedToken("|".to_string()))
+        );
+        assert_eq!(
+            parse("a >"),
+            Err(ParseError::UnexpectedToken("newline".to_string()))
+        );
+    }
+
+    #[test]
+    fn quoted_operators_stay_in_words() {
+        let program = parse(r#"grep "x|y" 'a;b' c\&d"#).unwrap();
+        let pipeline = &program.items[0].first;
+        assert_eq!(pipeline.commands.len(), 1);
+        assert_eq!(words(&pipeline.commands[0]), ["grep", "x|y", "a;b", "c&d"]);
+    }
+
+    #[test]
+    fn background_lists() {
+        let program = parse("a & b; c &").unwrap();
+        let background: Vec<bool> = program.items.iter().map(|l| l.background).collect();
+        assert_eq!(background, [true, false, true]);
+    }
+
+    #[test]
+    fn assignments_only_before_the_name() {
+        let program = parse("x=1 y=2 cmd a=b").unwrap();
+        let command = &program.items[0].first.commands[0];
+        assert_eq!(command.assignments.len(), 2);
+        assert_eq!(words(command), ["cmd", "a=b"]);
+    }
+
+    #[test]
+    fn redirections_apply_in_written_order() {
+        let program = parse("a 2>&1 >f").unwrap();
+        let command = &program.items[0].first.commands[0];
+        let ops: Vec<_> = command.redirects.iter().map(|r| (r.fd, r.op)).collect();
+        assert_eq!(
+            ops,
+            [
+                (Some(2), crate::lexer::RedirOp::DupOutput),
+                (None, crate::lexer::RedirOp::Write),
+            ]
+        );
+    }
+
+    #[test]
+    fn pipe_all_adds_a_dup_after_other_redirections() {
+        let program = parse("a >f |& b").unwrap();
+        let command = &program.items[0].first.commands[0];
+        assert_eq!(command.redirects.len(), 2);
+        let last = &command.redirects[1];
+        assert_eq!(
+            (last.fd, last.op),
+            (Some(2), crate::lexer::RedirOp::DupOutput)
+        );
+        assert_eq!(last.target.unquoted(), "1");
+    }
+
+    #[test]
+    fn negation() {
+        assert!(parse("! a | b").unwrap().items[0].first.negated);
+        assert!(!parse("! ! a").unwrap().items[0].first.negated);
+        assert!(!parse("'!' a").unwrap().items[0].first.negated);
+    }
+}
@@ -0,0 +1,234 @@
+//! Shell pattern matching: `*`, `?` and bracket expressions, as used by
+//! `${v#pattern}` and friends and by pathname expansion.
+
+/// One piece of a parsed pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `[...]`
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    /// `[:digit:]` and the other POSIX classes.
+    Named(String),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Char(x) => *x == c,
+            ClassItem::Range(from, to) => (*from..=*to).contains(&c),
+            ClassItem::Named(name) => match name.as_str() {
+                "alnum" => c.is_alphanumeric(),
+                "alpha" => c.is_alphabetic(),
+                "blank" => c == ' ' || c == '\t',
+                "cntrl" => c.is_control(),
+                "digit" => c.is_ascii_digit(),
+                "graph" => !c.is_whitespace() && !c.is_control(),
+                "lower" => c.is_lowercase(),
+                "print" => !c.is_control(),
+                "punct" => c.is_ascii_punctuation(),
+                "space" => c.is_whitespace(),
+                "upper" => c.is_uppercase(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    /// Parses a pattern out of characters paired with whether they were
+    /// quoted. Quoted characters only ever match themselves.
+    pub fn new(chars: &[(char, bool)]) -> Self {
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let (c, quoted) = chars[i];
+            i += 1;
+            if quoted {
+                tokens.push(Token::Char(c));
+                continue;
+            }
+            match c {
+                '*' => {
+                    // `**` matches the same as `*`
+                    if tokens.last() != Some(&Token::Star) {
+                        tokens.push(Token::Star);
+                    }
+                }
+                '?' => tokens.push(Token::Any),
+                '[' => match parse_class(&chars[i..]) {
+                    Some((token, len)) => {
+                        tokens.push(token);
+                        i += len;
+                    }
+                    // No closing bracket: a plain `[`
+                    None => tokens.push(Token::Char('[')),
+                },
+                _ => tokens.push(Token::Char(c)),
+            }
+        }
+        Self { tokens }
+    }
+
+    /// Whether anything in the pattern matches more than one string.
+    pub fn has_wildcards(&self) -> bool {
+        self.tokens.iter().any(|t| !matches!(t, Token::Char(_)))
+    }
+
+    /// Whether the pattern matches all of `text`.
+    pub fn matches(&self, text: &[char]) -> bool {
+        // Backtracking only ever needs to go back to the last `*`
+        let mut t = 0;
+        let mut p = 0;
+        let mut star: Option<(usize, usize)> = None;
+        while t < text.len() {
+            match self.tokens.get(p) {
+                Some(Token::Star) => {
+                    star = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                Some(token) if token_matches(token, text[t]) => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            }
+        }
+        self.tokens[p..].iter().all(|t| *t == Token::Star)
+    }
+}
+
+fn token_matches(token: &Token, c: char) -> bool {
+    match token {
+        Token::Char(x) => *x == c,
+        Token::Any => true,
+        Token::Star => false,
+        Token::Class { negated, items } => items.iter().any(|i| i.matches(c)) != *negated,
+    }
+}
+
+/// Parses a bracket expression after its `[`. Returns the token and how
+/// many characters it used, or `None` if it is never closed.
+fn parse_class(chars: &[(char, bool)]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some(('!' | '^', false)));
+    if negated {
+        i += 1;
+    }
+
+    let mut items = Vec::new();
+    let start = i;
+    loop {
+        let &(c, quoted) = chars.get(i)?;
+        // A `]` right at the start is a member, not the end
+        if c == ']' && !quoted && i > start {
+            return Some((Token::Class { negated, items }, i + 1));
+        }
+        i += 1;
+
+        if c == '[' && !quoted && chars.get(i) == Some(&(':', false)) {
+            let rest: String = chars[i + 1..].iter().map(|&(c, _)| c).collect();
+            if let Some(end) = rest.find(":]") {
+                items.push(ClassItem::Named(rest[..end].to_string()));
+                i += 1 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let is_range = chars.get(i) == Some(&('-', false))
+            && chars.get(i + 1).is_some_and(|&(c, q)| c != ']' || q);
+        if is_range {
+            items.push(ClassItem::Range(c, chars[i + 1].0));
+            i += 2;
+        } else {
+            items.push(ClassItem::Char(c));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `pattern` with every character unquoted, except those after a `\`.
+    fn pattern(pattern: &str) -> Pattern {
+        let mut chars = Vec::new();
+        let mut escaped = false;
+        for c in pattern.chars() {
+            if c == '\\' && !escaped {
+                escaped = true;
+                continue;
+            }
+            chars.push((c, escaped));
+            escaped = false;
+        }
+        Pattern::new(&chars)
+    }
+
+    fn matches(p: &str, text: &str) -> bool {
+        pattern(p).matches(&text.chars().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("?x", "ax"));
+        assert!(!matches("?x", "x"));
+        assert!(matches("a*b*c", "aXbYbc"));
+    }
+
+    #[test]
+    fn bracket_expressions() {
+        assert!(matches("[a-c]*", "banana"));
+        assert!(!matches("[a-c]*", "dog"));
+        assert!(matches("[!a]b", "xb"));
+        assert!(!matches("[!a]b", "ab"));
+        assert!(matches("[[:digit:]]*", "9lives"));
+        assert!(matches("[]]", "]"));
+    }
+
+    #[test]
+    fn quoted_characters_match_themselves() {
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "x"));
+        assert!(matches(r"a\?", "a?"));
+    }
+
+    #[test]
+    fn has_wildcards() {
+        assert!(pattern("*.rs").has_wildcards());
+        assert!(pattern("[ab]").has_wildcards());
+        assert!(!pattern("plain").has_wildcards());
+        assert!(!pattern(r"\*").has_wildcards());
+    }
+}
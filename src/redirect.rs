@@ -0,0 +1,349 @@
+//! Redirections: opening the files they name and pointing a command's fds
+//! at them.
+//!
+//! Everything is worked out by the shell, so a missing file or a bad fd is
+//! reported before the command starts. The child then only replays the
+//! `dup2` and `close` calls, in the order the redirections were written.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::mem::{self, ManuallyDrop};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use thiserror::Error;
+
+use crate::ast::Redirect;
+use crate::expand::{ExpandError, expand_word};
+use crate::lexer::RedirOp;
+use crate::shell::Shell;
+use crate::sys;
+
+/// Files opened for redirections are kept at this fd or above, and above
+/// any larger number the redirections themselves use.
+const FIRST_PRIVATE_FD: i32 = 10;
+
+#[derive(Debug, Error)]
+pub enum RedirectError {
+    #[error(transparent)]
+    Expand(#[from] ExpandError),
+    /// A file that couldn't be opened, and why.
+    #[error("{0}: {1}")]
+    Open(String, String),
+    #[error("{0}: Bad file descriptor")]
+    BadFd(String),
+    #[error("{0}: ambiguous redirect")]
+    Ambiguous(String),
+    #[error("cannot create temp file for here-document: {0}")]
+    HereDoc(String),
+}
+
+/// What one redirection makes an fd refer to.
+enum Action {
+    Open(File),
+    /// Whatever another fd refers to at that point.
+    Dup(i32),
+    Close,
+}
+
+/// The redirections of one command, in the order they apply.
+#[derive(Default)]
+pub struct Redirections {
+    actions: Vec<(i32, Action)>,
+}
+
+impl Redirections {
+    pub fn new(redirects: &[Redirect], shell: &mut Shell) -> Result<Self, RedirectError> {
+        let mut redirections = Self::default();
+        for redirect in redirects {
+            let fd = redirect.fd.unwrap_or(redirect.op.default_fd()) as i32;
+            let target = expand_word(&redirect.target, shell)?;
+            match redirect.op {
+                RedirOp::DupInput | RedirOp::DupOutput => {
+                    if target == "-" {
+                        redirections.actions.push((fd, Action::Close));
+                    } else if !target.is_empty() && target.bytes().all(|b| b.is_ascii_digit()) {
+                        let source = target.parse().unwrap_or(-1);
+                        if !redirections.is_open(source) {
+                            return Err(RedirectError::BadFd(target));
+                        }
+                        redirections.actions.push((fd, Action::Dup(source)));
+                    } else if redirect.op == RedirOp::DupOutput && redirect.fd.is_none() {
+                        // `>&file` is an older spelling of `&>file`
+                        redirections.open_all(&target, false)?;
+                    } else {
+                        return Err(RedirectError::Ambiguous(target));
+                    }
+                }
+                RedirOp::HereDoc => {
+                    let file = here_file(&target)?;
+                    redirections.actions.push((fd, Action::Open(file)));
+                }
+                RedirOp::HereString => {
+                    let file = here_file(&format!("{}\n", target))?;
+                    redirections.actions.push((fd, Action::Open(file)));
+                }
+                RedirOp::WriteAll => redirections.open_all(&target, false)?,
+                RedirOp::AppendAll => redirections.open_all(&target, true)?,
+                op => {
+                    let file = open(&target, op)?;
+                    redirections.actions.push((fd, Action::Open(file)));
+                }
+            }
+        }
+        redirections.keep_files_clear()?;
+        Ok(redirections)
+    }
+
+    /// Moves the opened files above every fd the redirections name, so the
+    /// child's `dup2` calls can't replace one before it has been used.
+    fn keep_files_clear(&mut self) -> Result<(), RedirectError> {
+        let highest = self
+            .actions
+            .iter()
+            .map(|(fd, action)| match action {
+                Action::Dup(source) => (*fd).max(*source),
+                _ => *fd,
+            })
+            .max()
+            .unwrap_or(0);
+        if highest < FIRST_PRIVATE_FD {
+            return Ok(());
+        }
+
+        for (fd, action) in mem::take(&mut self.actions) {
+            let action = match action {
+                Action::Open(file) if file.as_raw_fd() <= highest => {
+                    let moved = sys::move_above(file, highest + 1);
+                    Action::Open(moved.map_err(|_| RedirectError::BadFd(highest.to_string()))?)
+                }
+                action => action,
+            };
+            self.actions.push((fd, action));
+        }
+        Ok(())
+    }
+
+    /// `&>file`: stdout to the file, and stderr to the same place.
+    fn open_all(&mut self, path: &str, append: bool) -> Result<(), RedirectError> {
+        let op = if append {
+            RedirOp::Append
+        } else {
+            RedirOp::Write
+        };
+        self.actions.push((1, Action::Open(open(path, op)?)));
+        self.actions.push((2, Action::Dup(1)));
+        Ok(())
+    }
+
+    /// Whether `fd` is open once the redirections so far have applied.
+    fn is_open(&self, fd: i32) -> bool {
+        match self.actions.iter().rev().find(|(f, _)| *f == fd) {
+            Some((_, Action::Close)) => false,
+            Some(_) => true,
+            // The files opened so far aren't there for the command to use
+            None => !self.holds(fd) && sys::is_open(fd),
+        }
+    }
+
+    /// Whether `fd` is one of the files opened for the redirections.
+    fn holds(&self, fd: i32) -> bool {
+        self.actions
+            .iter()
+            .any(|(_, action)| matches!(action, Action::Open(file) if file.as_raw_fd() == fd))
+    }
+
+    /// What `fd` ends up referring to.
+    pub fn handle(&self, fd: i32) -> Handle<'_> {
+        handle_in(&self.actions, fd)
+    }
+
+    /// Where a builtin run with these redirections writes.
+    pub fn builtin_io(&self) -> BuiltinIo<'_> {
+        BuiltinIo {
+            stdout: self.handle(1),
+            stderr: self.handle(2),
+        }
+    }
+
+    /// Makes the child started by `cmd` apply the redirections before it
+    /// execs. They apply after `cmd`'s own stdio setup, so they win over a
+    /// pipe.
+    pub fn prepare_command(&self, cmd: &mut Command) {
+        if self.actions.is_empty() {
+            return;
+        }
+        // Raw fds only: nothing is allocated between fork and exec
+        let steps: Vec<(i32, Option<i32>)> = self
+            .actions
+            .iter()
+            .map(|(fd, action)| match action {
+                Action::Open(file) => (*fd, Some(file.as_raw_fd())),
+                Action::Dup(source) => (*fd, Some(*source)),
+                Action::Close => (*fd, None),
+            })
+            .collect();
+        unsafe {
+            cmd.pre_exec(move || {
+                for &(fd, source) in &steps {
+                    match source {
+                        // dup2 onto itself would leave close-on-exec set
+                        Some(source) if source == fd => sys::keep_on_exec(fd)?,
+                        Some(source) => sys::duplicate_fd(source, fd)?,
+                        None => {
+                            let _ = sys::close_fd(fd);
+                        }
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// What `fd` refers to after `actions`.
+fn handle_in(actions: &[(i32, Action)], fd: i32) -> Handle<'_> {
+    let Some(index) = actions.iter().rposition(|(f, _)| *f == fd) else {
+        return Handle::Shell(fd);
+    };
+    match &actions[index].1 {
+        Action::Open(file) => Handle::File(file),
+        Action::Dup(source) => handle_in(&actions[..index], *source),
+        Action::Close => Handle::Closed,
+    }
+}
+
+/// An fd as a builtin sees it. Builtins run inside the shell, so instead of
+/// moving fds around they read and write through one of these.
+pub enum Handle<'a> {
+    /// One of the shell's own fds, left alone by the redirections.
+    Shell(i32),
+    File(&'a File),
+    Closed,
+}
+
+impl Handle<'_> {
+    fn with_file<T>(&self, f: impl FnOnce(&File) -> io::Result<T>) -> io::Result<T> {
+        match self {
+            Handle::Shell(fd) => {
+                // Borrowed, not owned: the fd stays open afterwards
+                let file = ManuallyDrop::new(unsafe { File::from_raw_fd(*fd) });
+                f(&file)
+            }
+            Handle::File(file) => f(file),
+            Handle::Closed => Err(io::Error::from_raw_os_error(sys::EBADF)),
+        }
+    }
+}
+
+impl Write for Handle<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            // Through std's handles, so output stays in order with print!
+            Handle::Shell(1) => io::stdout().write(buf),
+            Handle::Shell(2) => io::stderr().write(buf),
+            handle => handle.with_file(|mut file| file.write(buf)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Handle::Shell(1) => io::stdout().flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where a builtin writes its output and its errors.
+pub struct BuiltinIo<'a> {
+    pub stdout: Handle<'a>,
+    pub stderr: Handle<'a>,
+}
+
+impl BuiltinIo<'_> {
+    /// Writes a line of output. Builtins other than `echo` don't report
+    /// failing to, just like in bash.
+    pub fn println(&mut self, line: impl fmt::Display) {
+        let _ = writeln!(self.stdout, "{}", line);
+    }
+
+    /// Writes an error message.
+    pub fn eprintln(&mut self, line: impl fmt::Display) {
+        let _ = writeln!(self.stderr, "{}", line);
+    }
+}
+
+/// A file holding `text`, to be read from the start. It is unlinked right
+/// away, so nothing is left behind however the command ends.
+fn here_file(text: &str) -> Result<File, RedirectError> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let failed = |err| RedirectError::HereDoc(sys::error_message(&err));
+
+    let (path, mut file) = loop {
+        let name = format!(
+            "rust-shell-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = env::temp_dir().join(name);
+        let opened = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        match opened {
+            Ok(file) => break (path, file),
+            // Left over by an earlier shell with the same pid
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(failed(err)),
+        }
+    };
+    let _ = fs::remove_file(&path);
+    file.write_all(text.as_bytes()).map_err(failed)?;
+    file.seek(SeekFrom::Start(0)).map_err(failed)?;
+    sys::move_above(file, FIRST_PRIVATE_FD).map_err(failed)
+}
+
+fn open(path: &str, op: RedirOp) -> Result<File, RedirectError> {
+    let mut options = OpenOptions::new();
+    match op {
+        RedirOp::Read => options.read(true),
+        RedirOp::ReadWrite => options.read(true).write(true).create(true),
+        RedirOp::Append => options.append(true).create(true),
+        _ => options.write(true).create(true).truncate(true),
+    };
+    let failed = |err| RedirectError::Open(path.to_string(), sys::error_message(&err));
+    let file = options.open(path).map_err(failed)?;
+    sys::move_above(file, FIRST_PRIVATE_FD).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::AsRawFd;
+
+    use super::{Action, Redirections};
+    use crate::parser;
+    use crate::shell::Shell;
+
+    fn redirections(input: &str, shell: &mut Shell) -> Redirections {
+        let program = parser::parse(input).unwrap();
+        let command = &program.items[0].first.commands[0];
+        Redirections::new(&command.redirects, shell).unwrap()
+    }
+
+    #[test]
+    fn files_stay_clear_of_target_fds() {
+        // `11>a 10>b` used to open a at 10, which `10>b` then replaced
+        let mut shell = Shell::default();
+        let redirections = redirections("sh 11>/dev/null 10>/dev/null 12>&10", &mut shell);
+        for (_, action) in &redirections.actions {
+            if let Action::Open(file) = action {
+                assert!(file.as_raw_fd() > 12);
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! State that lives for the whole shell session.
+
+use crate::history::History;
+use crate::jobs::JobTable;
+use crate::options::Options;
+use crate::sys::Termios;
+use crate::variables::Variables;
+
+#[derive(Default)]
+pub struct Shell {
+    /// Exit status of the last pipeline, exposed as `$?`.
+    pub last_status: i32,
+    /// `PIPESTATUS`: the exit status of each command of the last pipeline
+    /// that ran in the foreground.
+    pub pipe_status: Vec<i32>,
+    /// `$0`: the script being run, or the shell's own name.
+    pub script_name: String,
+    /// `$1`, `$2`, ...
+    pub positional: Vec<String>,
+    pub vars: Variables,
+    /// Set with `shopt` and `set -o`.
+    pub options: Options,
+    /// `$$`: the pid of the shell itself, which subshells keep reporting.
+    pub pid: i32,
+    /// Status of the last command substitution, which is what a command made
+    /// only of assignments returns.
+    pub last_substitution_status: Option<i32>,
+    /// `$!`: the last process started in the background.
+    pub last_background_pid: Option<i32>,
+    /// Reading commands from the terminal. Errors that would end a script
+    /// only abandon the current command.
+    pub interactive: bool,
+    /// Whether pipelines get their own process group and the terminal.
+    pub job_control: bool,
+    /// The shell's own process group, which gets the terminal back after a
+    /// foreground job.
+    pub pgid: i32,
+    /// Terminal attributes from startup, put back after every foreground job
+    /// in case it left the terminal in a strange state.
+    pub tty_modes: Option<Termios>,
+    pub jobs: JobTable,
+    pub history: History,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        let mut vars = Variables::from_env();
+        // An inherited `PWD` can be missing or name some other directory
+        if let Ok(dir) = std::env::current_dir() {
+            let stale = vars
+                .get("PWD")
+                .is_none_or(|pwd| std::fs::canonicalize(pwd).ok().as_ref() != Some(&dir));
+            if stale {
+                vars.set("PWD", dir.to_string_lossy().into_owned());
+                vars.export("PWD");
+            }
+        }
+        Self {
+            vars,
+            pid: std::process::id() as i32,
+            ..Self::default()
+        }
+    }
+}
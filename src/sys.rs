@@ -0,0 +1,257 @@
+//! Thin wrappers around the libc calls std doesn't expose.
+//!
+//! Constants are the Linux values.
+
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::raw::c_char;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGPIPE: i32 = 13;
+pub const SIGCONT: i32 = 18;
+pub const SIGTSTP: i32 = 20;
+pub const SIGTTIN: i32 = 21;
+pub const SIGTTOU: i32 = 22;
+
+/// Signals an interactive shell ignores so that only the foreground job gets
+/// them. Children have to put them back to the default.
+pub const JOB_CONTROL_SIGNALS: [i32; 5] = [SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU];
+
+pub const EBADF: i32 = 9;
+
+pub const WNOHANG: i32 = 1;
+pub const WUNTRACED: i32 = 2;
+pub const WCONTINUED: i32 = 8;
+
+const TCSADRAIN: i32 = 1;
+
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const F_DUPFD_CLOEXEC: i32 = 1030;
+
+const POLLIN: i16 = 1;
+
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+
+unsafe extern "C" {
+    fn setpgid(pid: i32, pgid: i32) -> i32;
+    fn getpgrp() -> i32;
+    fn tcsetpgrp(fd: i32, pgrp: i32) -> i32;
+    fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+    fn kill(pid: i32, sig: i32) -> i32;
+    fn isatty(fd: i32) -> i32;
+    fn fork() -> i32;
+    fn dup2(old: i32, new: i32) -> i32;
+    fn close(fd: i32) -> i32;
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+    fn _exit(status: i32) -> !;
+    fn strsignal(sig: i32) -> *const c_char;
+    fn strerror(errnum: i32) -> *const c_char;
+    fn signal(sig: i32, handler: usize) -> usize;
+    fn tcgetattr(fd: i32, termios: *mut Termios) -> i32;
+    fn tcsetattr(fd: i32, action: i32, termios: *const Termios) -> i32;
+    fn cfmakeraw(termios: *mut Termios);
+    fn poll(fds: *mut PollFd, nfds: u64, timeout: i32) -> i32;
+    fn getuid() -> u32;
+    fn getpwnam(name: *const c_char) -> *const Passwd;
+    fn getpwuid(uid: u32) -> *const Passwd;
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+/// An entry of the passwd database, as glibc lays it out.
+#[repr(C)]
+struct Passwd {
+    name: *const c_char,
+    password: *const c_char,
+    uid: u32,
+    gid: u32,
+    gecos: *const c_char,
+    dir: *const c_char,
+    shell: *const c_char,
+}
+
+/// Terminal attributes. Only ever filled in and read back by libc, so it is
+/// kept opaque; the buffer is larger than any platform's `struct termios`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Termios([u32; 32]);
+
+pub fn get_termios(fd: i32) -> io::Result<Termios> {
+    let mut termios = Termios([0; 32]);
+    check(unsafe { tcgetattr(fd, &mut termios) })?;
+    Ok(termios)
+}
+
+/// Applies `termios` once pending output has been written.
+pub fn set_termios(fd: i32, termios: &Termios) -> io::Result<()> {
+    check(unsafe { tcsetattr(fd, TCSADRAIN, termios) }).map(|_| ())
+}
+
+/// Raw mode as in `stty raw -echo`: no line buffering, echo, signal keys or
+/// output post-processing.
+pub fn make_raw(termios: &mut Termios) {
+    unsafe { cfmakeraw(termios) }
+}
+
+/// Waits up to `timeout_ms` for `fd` to have input. Only meaningful for
+/// unbuffered reads: bytes already in a `BufReader` don't count.
+pub fn has_input(fd: i32, timeout_ms: i32) -> bool {
+    let mut pollfd = PollFd {
+        fd,
+        events: POLLIN,
+        revents: 0,
+    };
+    unsafe { poll(&mut pollfd, 1, timeout_ms) > 0 }
+}
+
+fn check(ret: i32) -> io::Result<i32> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+pub fn is_tty(fd: i32) -> bool {
+    unsafe { isatty(fd) == 1 }
+}
+
+pub fn set_process_group(pid: i32, pgid: i32) -> io::Result<()> {
+    check(unsafe { setpgid(pid, pgid) }).map(|_| ())
+}
+
+pub fn process_group() -> i32 {
+    unsafe { getpgrp() }
+}
+
+/// Hands the terminal on stdin to `pgid`.
+///
+/// Only works while SIGTTOU is ignored: when the shell takes the terminal back
+/// it is still in the background, and would otherwise be stopped.
+pub fn set_foreground(pgid: i32) -> io::Result<()> {
+    check(unsafe { tcsetpgrp(0, pgid) }).map(|_| ())
+}
+
+pub fn ignore_signal(sig: i32) {
+    unsafe {
+        signal(sig, SIG_IGN);
+    }
+}
+
+/// Restores the default action. Safe to call between fork and exec.
+pub fn default_signal(sig: i32) {
+    unsafe {
+        signal(sig, SIG_DFL);
+    }
+}
+
+pub fn send_signal(pid: i32, signal: i32) -> io::Result<()> {
+    check(unsafe { kill(pid, signal) }).map(|_| ())
+}
+
+/// `waitpid`, returning `None` when `WNOHANG` is set and nothing changed.
+pub fn wait_pid(pid: i32, options: i32) -> io::Result<Option<(i32, ExitStatus)>> {
+    let mut status = 0;
+    match check(unsafe { waitpid(pid, &mut status, options) })? {
+        0 => Ok(None),
+        pid => Ok(Some((pid, ExitStatus::from_raw(status)))),
+    }
+}
+
+/// Forks the shell. Returns the child's pid in the parent and 0 in the child.
+pub fn fork_process() -> io::Result<i32> {
+    check(unsafe { fork() })
+}
+
+/// Makes `new` refer to the same file as `old`, closing what it was before.
+pub fn duplicate_fd(old: i32, new: i32) -> io::Result<()> {
+    check(unsafe { dup2(old, new) }).map(|_| ())
+}
+
+/// Closes `fd`. Safe to call between fork and exec.
+pub fn close_fd(fd: i32) -> io::Result<()> {
+    check(unsafe { close(fd) }).map(|_| ())
+}
+
+pub fn is_open(fd: i32) -> bool {
+    unsafe { fcntl(fd, F_GETFD) != -1 }
+}
+
+/// Lets `fd` stay open across exec. Safe to call between fork and exec.
+pub fn keep_on_exec(fd: i32) -> io::Result<()> {
+    check(unsafe { fcntl(fd, F_SETFD, 0) }).map(|_| ())
+}
+
+/// Moves `file` to an fd number of at least `min`, out of the way of the
+/// small numbers redirections use.
+pub fn move_above(file: File, min: i32) -> io::Result<File> {
+    let fd = check(unsafe { fcntl(file.as_raw_fd(), F_DUPFD_CLOEXEC, min) })?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Leaves a forked child without running any of the parent's cleanup.
+pub fn exit_now(status: i32) -> ! {
+    unsafe { _exit(status) }
+}
+
+/// Home directory of `user` from the passwd database.
+pub fn user_home(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    unsafe { home_of(getpwnam(name.as_ptr())) }
+}
+
+/// Home directory of the user running the shell, for when `HOME` is unset.
+pub fn current_user_home() -> Option<String> {
+    unsafe { home_of(getpwuid(getuid())) }
+}
+
+/// # Safety
+/// `entry` has to be null or what `getpwnam`/`getpwuid` just returned.
+unsafe fn home_of(entry: *const Passwd) -> Option<String> {
+    if entry.is_null() || unsafe { (*entry).dir.is_null() } {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr((*entry).dir) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Human readable name of a signal, such as "Terminated".
+pub fn signal_name(signal: i32) -> String {
+    unsafe {
+        let name = strsignal(signal);
+        if name.is_null() {
+            return format!("Signal {}", signal);
+        }
+        CStr::from_ptr(name).to_string_lossy().into_owned()
+    }
+}
+
+/// The bare libc description of an error, without the " (os error N)" that
+/// `io::Error` appends.
+pub fn error_message(err: &io::Error) -> String {
+    let Some(errno) = err.raw_os_error() else {
+        return err.to_string();
+    };
+    unsafe {
+        let message = strerror(errno);
+        if message.is_null() {
+            return err.to_string();
+        }
+        CStr::from_ptr(message).to_string_lossy().into_owned()
+    }
+}
@@ -0,0 +1,28 @@
+//! Raw mode for the line editor.
+
+use std::io;
+
+use crate::sys::{self, Termios};
+
+/// Keeps stdin in raw mode while alive. Dropping it, including while
+/// unwinding from a panic, restores the attributes it started from.
+pub struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    /// Fails when stdin is not a terminal.
+    pub fn enable() -> io::Result<Self> {
+        let original = sys::get_termios(0)?;
+        let mut raw = original;
+        sys::make_raw(&mut raw);
+        sys::set_termios(0, &raw)?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = sys::set_termios(0, &self.original);
+    }
+}
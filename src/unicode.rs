@@ -0,0 +1,263 @@
+//! Just enough Unicode for the line editor: where grapheme clusters start and
+//! how many terminal columns they take.
+//!
+//! The tables are a compact approximation of UAX #29 and East Asian Width:
+//! combining marks, emoji sequences and flags, and the CJK blocks.
+
+const ZWJ: char = '\u{200D}';
+/// Variation selector 16, which asks for the emoji (wide) presentation.
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+
+/// Characters that attach to the one before them.
+const EXTENDING: &[(u32, u32)] = &[
+    (0x0300, 0x036F),
+    (0x0483, 0x0489),
+    (0x0591, 0x05BD),
+    (0x05BF, 0x05BF),
+    (0x05C1, 0x05C2),
+    (0x05C4, 0x05C5),
+    (0x05C7, 0x05C7),
+    (0x0610, 0x061A),
+    (0x064B, 0x065F),
+    (0x0670, 0x0670),
+    (0x06D6, 0x06DC),
+    (0x06DF, 0x06E4),
+    (0x06E7, 0x06E8),
+    (0x06EA, 0x06ED),
+    (0x0900, 0x0903),
+    (0x093A, 0x094F),
+    (0x0951, 0x0957),
+    (0x0962, 0x0963),
+    (0x0E31, 0x0E31),
+    (0x0E34, 0x0E3A),
+    (0x0E47, 0x0E4E),
+    (0x1160, 0x11FF),
+    (0x1AB0, 0x1AFF),
+    (0x1DC0, 0x1DFF),
+    (0x200B, 0x200D),
+    (0x20D0, 0x20FF),
+    (0x302A, 0x302F),
+    (0x3099, 0x309A),
+    (0xFE00, 0xFE0F),
+    (0xFE20, 0xFE2F),
+    (0x1F3FB, 0x1F3FF),
+    (0xE0020, 0xE007F),
+    (0xE0100, 0xE01EF),
+];
+
+/// Characters terminals draw two columns wide.
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115F),
+    (0x231A, 0x231B),
+    (0x2329, 0x232A),
+    (0x23E9, 0x23EC),
+    (0x23F0, 0x23F0),
+    (0x23F3, 0x23F3),
+    (0x25FD, 0x25FE),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x267F, 0x267F),
+    (0x2693, 0x2693),
+    (0x26A1, 0x26A1),
+    (0x26AA, 0x26AB),
+    (0x26BD, 0x26BE),
+    (0x26C4, 0x26C5),
+    (0x26CE, 0x26CE),
+    (0x26D4, 0x26D4),
+    (0x26EA, 0x26EA),
+    (0x26F2, 0x26F3),
+    (0x26F5, 0x26F5),
+    (0x26FA, 0x26FA),
+    (0x26FD, 0x26FD),
+    (0x2705, 0x2705),
+    (0x270A, 0x270B),
+    (0x2728, 0x2728),
+    (0x274C, 0x274C),
+    (0x274E, 0x274E),
+    (0x2753, 0x2755),
+    (0x2757, 0x2757),
+    (0x2795, 0x2797),
+    (0x27B0, 0x27B0),
+    (0x27BF, 0x27BF),
+    (0x2B1B, 0x2B1C),
+    (0x2B50, 0x2B50),
+    (0x2B55, 0x2B55),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xA960, 0xA97F),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE10, 0xFE19),
+    (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x1F004, 0x1F004),
+    (0x1F0CF, 0x1F0CF),
+    (0x1F18E, 0x1F18E),
+    (0x1F191, 0x1F19A),
+    (0x1F1E6, 0x1F1FF),
+    (0x1F200, 0x1F251),
+    (0x1F300, 0x1F64F),
+    (0x1F680, 0x1F6FF),
+    (0x1F7E0, 0x1F7EB),
+    (0x1F90C, 0x1F9FF),
+    (0x1FA70, 0x1FAFF),
+    (0x20000, 0x2FFFD),
+    (0x30000, 0x3FFFD),
+];
+
+fn in_table(c: char, table: &[(u32, u32)]) -> bool {
+    let c = c as u32;
+    table
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                std::cmp::Ordering::Less
+            } else if start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+fn is_extending(c: char) -> bool {
+    in_table(c, EXTENDING)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+/// Columns taken by a single character, as `shown` prints it.
+fn char_width(c: char) -> usize {
+    if c.is_ascii_control() {
+        2
+    } else if c.is_control() || is_extending(c) {
+        0
+    } else if in_table(c, WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Whether `c` is part of a word for word motion: combining marks count as
+/// part of the letter they sit on.
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || is_extending(c)
+}
+
+/// Index of the first character after the cluster that starts at `pos`.
+pub fn next_boundary(chars: &[char], pos: usize) -> usize {
+    if pos >= chars.len() {
+        return chars.len();
+    }
+    let mut end = pos + 1;
+    if is_regional_indicator(chars[pos])
+        && chars.get(end).is_some_and(|&c| is_regional_indicator(c))
+    {
+        // A flag is a pair of regional indicators
+        end += 1;
+    }
+    while end < chars.len()
+        && (is_extending(chars[end]) || (chars[end - 1] == ZWJ && char_width(chars[end]) == 2))
+    {
+        end += 1;
+    }
+    end
+}
+
+/// Start of the cluster that ends at `pos`.
+pub fn prev_boundary(chars: &[char], pos: usize) -> usize {
+    // Clusters can only be found going forwards
+    let mut start = 0;
+    while start < pos {
+        let end = next_boundary(chars, start);
+        if end >= pos {
+            break;
+        }
+        start = end;
+    }
+    start
+}
+
+/// Columns `chars` takes on the terminal.
+pub fn width(chars: &[char]) -> usize {
+    let mut total = 0;
+    let mut pos = 0;
+    while pos < chars.len() {
+        let end = next_boundary(chars, pos);
+        let cluster = &chars[pos..end];
+        total += if cluster.contains(&EMOJI_PRESENTATION) {
+            2
+        } else {
+            char_width(cluster[0])
+        };
+        pos = end;
+    }
+    total
+}
+
+/// `chars` the way the editor prints them: ASCII control characters, which
+/// would move the terminal's cursor, in caret notation, like `^J` for a
+/// newline.
+pub fn shown(chars: &[char]) -> String {
+    let mut text = String::new();
+    for &c in chars {
+        if c.is_ascii_control() {
+            text.push('^');
+            text.push((c as u8 ^ 0x40) as char);
+        } else {
+            text.push(c);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn clusters() {
+        let accented = chars("e\u{301}x");
+        assert_eq!(next_boundary(&accented, 0), 2);
+        assert_eq!(next_boundary(&accented, 2), 3);
+        assert_eq!(next_boundary(&accented, 3), 3);
+        assert_eq!(prev_boundary(&accented, 2), 0);
+
+        assert_eq!(next_boundary(&chars("🇫🇷!"), 0), 2);
+        assert_eq!(next_boundary(&chars("👩\u{200d}💻!"), 0), 3);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(width(&chars("abc")), 3);
+        assert_eq!(width(&chars("日本")), 4);
+        assert_eq!(width(&chars("e\u{301}")), 1);
+        assert_eq!(width(&chars("👍")), 2);
+        assert_eq!(width(&chars("🇫🇷")), 2);
+    }
+
+    #[test]
+    fn word_chars() {
+        assert!(is_word_char('a'));
+        assert!(is_word_char('\u{301}'));
+        assert!(!is_word_char('-'));
+    }
+
+    #[test]
+    fn control_characters_in_caret_notation() {
+        let text = chars("a\nb\x7f");
+        assert_eq!(shown(&text), "a^Jb^?");
+        assert_eq!(width(&text), 6);
+    }
+}
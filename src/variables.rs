@@ -0,0 +1,213 @@
+//! Shell variables, set with `NAME=value` and read with `$NAME`.
+//!
+//! This is the shell's own copy of the environment: the process environment
+//! is only read once at startup. Children get the exported variables passed
+//! explicitly.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+use crate::redirect::BuiltinIo;
+
+/// A variable's value from before `Variables::set_temporarily`.
+pub struct Saved {
+    name: String,
+    value: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Variables {
+    values: HashMap<String, String>,
+    /// Names marked by `export`. They may not have a value yet.
+    exported: HashSet<String>,
+}
+
+impl Variables {
+    /// Starts out with the process environment, all of it exported. `IFS` is
+    /// not taken from it, like every other shell does, so an odd inherited
+    /// value can't change how scripts split words.
+    pub fn from_env() -> Self {
+        let values: HashMap<String, String> = env::vars()
+            .filter(|(name, _)| name != "IFS" && is_valid_name(name))
+            .collect();
+        let exported = values.keys().cloned().collect();
+        Self { values, exported }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn export(&mut self, name: &str) {
+        self.exported.insert(name.to_string());
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.values.remove(name);
+        self.exported.remove(name);
+    }
+
+    /// Sets `name` until the result is given back to `restore`: what a
+    /// `NAME=value` prefix does for a builtin.
+    pub fn set_temporarily(&mut self, name: &str, value: String) -> Saved {
+        Saved {
+            name: name.to_string(),
+            value: self.values.insert(name.to_string(), value),
+        }
+    }
+
+    pub fn restore(&mut self, saved: Saved) {
+        match saved.value {
+            Some(value) => self.values.insert(saved.name, value),
+            None => self.values.remove(&saved.name),
+        };
+    }
+
+    /// The exported variables that have a value, sorted by name: what child
+    /// processes get as their environment.
+    pub fn environment(&self) -> Vec<(&str, &str)> {
+        let mut vars: Vec<(&str, &str)> = self
+            .exported
+            .iter()
+            .filter_map(|name| Some((name.as_str(), self.get(name)?)))
+            .collect();
+        vars.sort();
+        vars
+    }
+}
+
+/// Letters, digits and underscores, not starting with a digit.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// `export [-p] [NAME[=value]...]`
+pub fn builtin_export(vars: &mut Variables, args: &[String], io: &mut BuiltinIo) -> i32 {
+    let names: Vec<&String> = args.iter().filter(|a| *a != "-p").collect();
+    if names.is_empty() {
+        print_exports(vars, io);
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_valid_name(name) {
+            io.eprintln(format!("export: `{}': not a valid identifier", arg));
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            vars.set(name, value.to_string());
+        }
+        vars.export(name);
+    }
+    status
+}
+
+/// Lists exported variables the way `export -p` does, as commands that
+/// would recreate them.
+fn print_exports(vars: &Variables, io: &mut BuiltinIo) {
+    let mut names: Vec<&String> = vars.exported.iter().collect();
+    names.sort();
+    for name in names {
+        match vars.get(name) {
+            Some(value) => {
+                let mut quoted = String::new();
+                for c in value.chars() {
+                    if matches!(c, '\\' | '"' | '$' | '`') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(c);
+                }
+                io.println(format!("declare -x {}=\"{}\"", name, quoted));
+            }
+            None => io.println(format!("declare -x {}", name)),
+        }
+    }
+}
+
+/// `unset [-v] NAME...`. There are no functions, so `-f` unsets nothing.
+pub fn builtin_unset(vars: &mut Variables, args: &[String], io: &mut BuiltinIo) -> i32 {
+    if args.first().is_some_and(|a| a == "-f") {
+        return 0;
+    }
+
+    let mut status = 0;
+    for name in args.iter().filter(|a| *a != "-v") {
+        if is_valid_name(name) {
+            vars.unset(name);
+        } else {
+            io.eprintln(format!("unset: `{}': not a valid identifier", name));
+            status = 1;
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        assert!(is_valid_name("_a1"));
+        assert!(is_valid_name("PATH"));
+        assert!(!is_valid_name("1a"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("a-b"));
+    }
+
+    #[test]
+    fn environment_holds_exported_values() {
+        let mut vars = Variables::default();
+        vars.set("B", "2".to_string());
+        vars.set("A", "1".to_string());
+        vars.set("LOCAL", "x".to_string());
+        vars.export("B");
+        vars.export("A");
+        vars.export("LATER");
+        assert_eq!(vars.environment(), [("A", "1"), ("B", "2")]);
+
+        vars.set("LATER", "3".to_string());
+        assert_eq!(vars.environment(), [("A", "1"), ("B", "2"), ("LATER", "3")]);
+    }
+
+    #[test]
+    fn unset_forgets_the_export() {
+        let mut vars = Variables::default();
+        vars.set("X", "1".to_string());
+        vars.export("X");
+        vars.unset("X");
+        assert_eq!(vars.get("X"), None);
+
+        vars.set("X", "2".to_string());
+        assert!(vars.environment().is_empty());
+    }
+
+    #[test]
+    fn restore_undoes_a_temporary_value() {
+        let mut vars = Variables::default();
+        vars.set("HOME", "/home/me".to_string());
+        let home = vars.set_temporarily("HOME", "/tmp".to_string());
+        let new = vars.set_temporarily("NEW", "1".to_string());
+        assert_eq!(vars.get("HOME"), Some("/tmp"));
+        assert_eq!(vars.get("NEW"), Some("1"));
+
+        vars.restore(new);
+        vars.restore(home);
+        assert_eq!(vars.get("HOME"), Some("/home/me"));
+        assert_eq!(vars.get("NEW"), None);
+    }
+}